@@ -0,0 +1,312 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use headers::{Cookie, HeaderMapExt};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::utility;
+
+
+// The cookie that proves the browser already entered the session PIN
+pub const ACCESS_COOKIE: &str = "slideshow_access";
+
+// PIN attempts an address gets before it has to wait
+const MAX_PIN_ATTEMPTS: u32 = 5;
+
+// The first wait, doubled for every attempt after that, up to the longest one
+const PIN_LOCKOUT: Duration = Duration::from_secs(30);
+const MAX_PIN_LOCKOUT: Duration = Duration::from_secs(60 * 60);
+
+
+// Query parameters accepted by the routes that let a client in.
+// `pin` is what the QR code embeds, `token` is for clients that can't send cookies (e.g. over WebSocket)
+#[derive(Deserialize)]
+pub struct AccessQuery {
+    pub pin: Option<String>,
+    pub token: Option<String>
+}
+
+// The PIN attempts made from one address since its last right PIN
+#[derive(Default)]
+pub struct PinAttempts {
+    attempts: u32,
+    locked_until: Option<Instant>
+}
+
+// The remote control opens /controller?key= and connects to the WebSocket with ?controller=
+#[derive(Deserialize)]
+pub struct ControllerQuery {
+    pub key: Option<String>,
+    pub controller: Option<String>
+}
+
+
+// A fresh 6 digit PIN for a new session
+pub fn generate_pin() -> String {
+
+    format!("{:06}", rand::thread_rng().gen_range(0..1_000_000))
+}
+
+
+// The random key the access tokens are signed with, new every time the app starts
+pub fn generate_secret() -> [u8; 32] {
+
+    rand::thread_rng().gen()
+}
+
+
+// The remote control link's key. Long enough that it can't be guessed, unlike the PIN
+pub fn generate_controller_key() -> String {
+
+    hex::encode(rand::thread_rng().gen::<[u8; 16]>())
+}
+
+
+// Whether the key opens the remote control. Compared in constant time, like the access tokens
+pub async fn is_controller_key(current_state: &utility::CurrentSlideState, candidate: &str) -> bool {
+
+    let key = current_state.controller_key.lock().await;
+
+    key.len() == candidate.len()
+        && key.bytes().zip(candidate.bytes()).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+
+impl PinAttempts {
+
+    // Counts one more attempt, or says how long the address still has to wait.
+    // Attempts are counted before the PIN is checked, so a burst of parallel requests can't slip past the limit
+    fn try_attempt(&mut self, now: Instant) -> Result<(), Duration> {
+
+        if let Some(until) = self.locked_until.filter(|until| *until > now) {
+            return Err(until - now);
+        }
+
+        self.attempts += 1;
+
+        if self.attempts >= MAX_PIN_ATTEMPTS {
+
+            let doublings = self.attempts - MAX_PIN_ATTEMPTS;
+            let wait = PIN_LOCKOUT.saturating_mul(2u32.saturating_pow(doublings)).min(MAX_PIN_LOCKOUT);
+
+            self.locked_until = Some(now + wait);
+        }
+
+        Ok(())
+    }
+}
+
+
+// Lets the address try a PIN, unless it guessed wrong too often lately
+pub async fn count_pin_attempt(current_state: &utility::CurrentSlideState, ip: IpAddr) -> Result<(), Duration> {
+
+    current_state.pin_attempts.lock().await
+        .entry(ip)
+        .or_default()
+        .try_attempt(Instant::now())
+}
+
+
+// The right PIN wipes the slate clean
+pub async fn forget_pin_attempts(current_state: &utility::CurrentSlideState, ip: IpAddr) {
+
+    current_state.pin_attempts.lock().await.remove(&ip);
+}
+
+
+// The access token is the PIN signed with the app's secret, so changing the PIN invalidates every cookie
+pub fn access_token(secret: &[u8], pin: &str) -> String {
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(pin.as_bytes());
+
+    hex::encode(mac.finalize().into_bytes())
+}
+
+
+fn is_valid_token(secret: &[u8], pin: &str, token: &str) -> bool {
+
+    let Ok(signature) = hex::decode(token) else {
+        return false;
+    };
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(pin.as_bytes());
+
+    // verify_slice compares in constant time
+    mac.verify_slice(&signature).is_ok()
+}
+
+
+// Whether the request may see the slides: no PIN set, the presenter's own machine,
+// or a valid token in either the cookie or the `token` query parameter
+pub async fn is_allowed(
+    current_state: &utility::CurrentSlideState,
+    peer: SocketAddr,
+    headers: &HeaderMap,
+    query_token: Option<&str>
+) -> bool {
+
+    let pin = match current_state.session_pin.lock().await.clone() {
+        Some(p) => p,
+        None => return true
+    };
+
+    if is_trusted_peer(current_state, peer).await {
+        return true;
+    }
+
+    let cookie_token = headers
+        .typed_get::<Cookie>()
+        .and_then(|cookie| cookie.get(ACCESS_COOKIE).map(|t| t.to_string()));
+
+    cookie_token.as_deref().into_iter()
+        .chain(query_token)
+        .any(|token| is_valid_token(&current_state.access_secret, &pin, token))
+}
+
+
+// The presenter's windows load slides from this machine, and they never see the PIN page
+pub async fn is_trusted_peer(current_state: &utility::CurrentSlideState, peer: SocketAddr) -> bool {
+
+    let host_ip = *current_state.host_ip.lock().await;
+
+    peer.ip().is_loopback() || Some(peer.ip()) == host_ip
+}
+
+
+// The Set-Cookie value for a client that just entered the right PIN
+pub fn access_cookie(current_state: &utility::CurrentSlideState, pin: &str, secure: bool) -> String {
+
+    format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax{}",
+        ACCESS_COOKIE,
+        access_token(&current_state.access_secret, pin),
+        if secure { "; Secure" } else { "" }
+    )
+}
+
+
+// Middleware for every route on both servers: addresses the presenter blocked get a 403
+pub async fn reject_blocked(
+    State(current_state): State<utility::CurrentSlideState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next
+) -> Response {
+
+    if current_state.blocked_addresses.lock().await.contains(&peer.ip()) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    next.run(request).await
+}
+
+
+// Middleware for the slide routes: anyone without the signed cookie gets a 401
+pub async fn require_access(
+    State(current_state): State<utility::CurrentSlideState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next
+) -> Response {
+
+    if is_allowed(&current_state, peer, request.headers(), None).await {
+        next.run(request).await
+    } else {
+        StatusCode::UNAUTHORIZED.into_response()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_only_match_their_pin_and_secret() {
+        let secret = [7u8; 32];
+        let token = access_token(&secret, "123456");
+
+        assert!(is_valid_token(&secret, "123456", &token));
+        assert!(!is_valid_token(&secret, "654321", &token));
+        assert!(!is_valid_token(&[8u8; 32], "123456", &token));
+        assert!(!is_valid_token(&secret, "123456", "not hex"));
+        assert!(!is_valid_token(&secret, "123456", &token[..10]));
+    }
+
+    #[test]
+    fn pins_are_six_digits() {
+        for _ in 0..100 {
+            let pin = generate_pin();
+            assert_eq!(pin.len(), 6);
+            assert!(pin.bytes().all(|b| b.is_ascii_digit()));
+        }
+    }
+
+    #[test]
+    fn controller_keys_are_long_and_random() {
+        let key = generate_controller_key();
+
+        assert_eq!(key.len(), 32);
+        assert!(key.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_ne!(key, generate_controller_key());
+    }
+
+    #[tokio::test]
+    async fn only_the_current_controller_key_opens_the_remote() {
+        let state = std::sync::Arc::new(utility::AppState::new());
+        let key = state.controller_key.lock().await.clone();
+
+        assert!(is_controller_key(&state, &key).await);
+        assert!(!is_controller_key(&state, &key[..31]).await);
+        // Same length, one character off
+        let mut wrong = key.clone();
+        wrong.replace_range(31.., if key.ends_with('0') { "1" } else { "0" });
+        assert!(!is_controller_key(&state, &wrong).await);
+
+        assert!(!is_controller_key(&state, "").await);
+
+        // A replaced key stops working right away
+        *state.controller_key.lock().await = generate_controller_key();
+        assert!(!is_controller_key(&state, &key).await);
+    }
+
+    #[test]
+    fn pin_attempts_lock_out_with_growing_waits() {
+        let start = Instant::now();
+        let mut attempts = PinAttempts::default();
+
+        for _ in 0..MAX_PIN_ATTEMPTS {
+            assert!(attempts.try_attempt(start).is_ok());
+        }
+
+        // Everything after the limit waits, however many come in at once
+        assert_eq!(attempts.try_attempt(start), Err(PIN_LOCKOUT));
+        assert!(attempts.try_attempt(start + PIN_LOCKOUT / 2).is_err());
+
+        // One more try once the wait is over, then twice as long
+        let later = start + PIN_LOCKOUT;
+        assert!(attempts.try_attempt(later).is_ok());
+        assert_eq!(attempts.try_attempt(later), Err(PIN_LOCKOUT * 2));
+    }
+
+    #[test]
+    fn pin_lockout_is_capped() {
+        let mut attempts = PinAttempts { attempts: 40, locked_until: None };
+        let now = Instant::now();
+
+        assert!(attempts.try_attempt(now).is_ok());
+        assert_eq!(attempts.try_attempt(now), Err(MAX_PIN_LOCKOUT));
+    }
+}
@@ -0,0 +1,310 @@
+use std::path::PathBuf;
+
+use xcap::image::{self, Rgba, RgbaImage};
+
+use crate::manifest;
+use crate::protocol;
+use crate::utility;
+
+
+// Longer strokes are cut off, a pen held down for minutes shouldn't turn into a huge message
+const MAX_POINTS: usize = 2000;
+
+// Stroke widths are a fraction of the slide width
+const MIN_WIDTH: f64 = 0.001;
+const MAX_WIDTH: f64 = 0.05;
+
+
+// Adds a stroke to the slide, renders the annotated copy and tells everyone about it.
+// Points are fractions of the slide (0..1 from the top left corner), like the laser pointer's
+pub async fn add_stroke(
+    current_state: &utility::CurrentSlideState,
+    slide: u16,
+    color: String,
+    width: f64,
+    points: Vec<[f64; 2]>
+) -> Result<protocol::Stroke, String> {
+
+    if points.is_empty() {
+        return Err("A stroke needs at least one point".into());
+    }
+
+    if parse_color(&color).is_none() {
+        return Err("Colors have to look like #ff3f00".into());
+    }
+
+    // One change at a time, so the annotated copy on disk always matches the manifest
+    let _rendering = current_state.annotation_lock.lock().await;
+
+    let (stroke, strokes) = {
+
+        let manifest = current_state.manifest.lock().await;
+
+        let entry = manifest.slide(slide)
+            .filter(|entry| entry.deleted_at.is_none())
+            .ok_or("No such slide")?;
+
+        let id = manifest.slides.iter()
+            .flat_map(|entry| &entry.annotations)
+            .map(|stroke| stroke.id)
+            .max()
+            .unwrap_or(0) + 1;
+
+        let stroke = clamped_stroke(id, &color, width, points);
+
+        let mut strokes = entry.annotations.clone();
+        strokes.push(stroke.clone());
+
+        (stroke, strokes)
+    };
+
+    // Rendered before the stroke is recorded, so nobody is sent to an annotated copy that isn't there yet
+    render(PathBuf::from("assets"), slide, strokes).await?;
+
+    {
+        let mut manifest = current_state.manifest.lock().await;
+
+        if let Some(entry) = manifest.slide_mut(slide) {
+            entry.annotations.push(stroke.clone());
+        }
+
+        if let Err(e) = manifest.save().await {
+            eprintln!("Failed to save the manifest: {}", e);
+        }
+    }
+
+    let message = protocol::ServerMessage::AnnotationAdded(protocol::AnnotationAdded {
+        slide,
+        stroke: stroke.clone()
+    });
+
+    // Nobody listening is fine, the strokes are also in slidesInfo
+    let _ = current_state.broadcast(message).await;
+
+    Ok(stroke)
+}
+
+
+// The strokes on a slide, for the viewer to draw over it
+pub async fn strokes(current_state: &utility::CurrentSlideState, slide: u16) -> Result<Vec<protocol::Stroke>, String> {
+
+    current_state.manifest.lock().await
+        .slide(slide)
+        .map(|entry| entry.annotations.clone())
+        .ok_or_else(|| "No such slide".into())
+}
+
+
+// Removes every stroke from the slide
+pub async fn clear(current_state: &utility::CurrentSlideState, slide: u16) -> Result<(), String> {
+
+    let _rendering = current_state.annotation_lock.lock().await;
+
+    {
+        let mut manifest = current_state.manifest.lock().await;
+
+        let entry = manifest.slide_mut(slide).ok_or("No such slide")?;
+
+        if entry.annotations.is_empty() {
+            return Ok(());
+        }
+
+        entry.annotations.clear();
+
+        if let Err(e) = manifest.save().await {
+            eprintln!("Failed to save the manifest: {}", e);
+        }
+    }
+
+    if let Err(e) = tokio::fs::remove_file(PathBuf::from("assets").join(manifest::annotated_file(slide))).await {
+        eprintln!("Failed to remove the annotated copy of slide {}: {}", slide, e);
+    }
+
+    let message = protocol::ServerMessage::AnnotationCleared(protocol::AnnotationCleared { slide });
+
+    let _ = current_state.broadcast(message).await;
+
+    Ok(())
+}
+
+
+// Keeps what the viewer sent inside the slide and within reason
+fn clamped_stroke(id: u64, color: &str, width: f64, points: Vec<[f64; 2]>) -> protocol::Stroke {
+
+    protocol::Stroke {
+        id,
+        color: color.to_lowercase(),
+        width: width.clamp(MIN_WIDTH, MAX_WIDTH),
+        points: points.into_iter()
+            .take(MAX_POINTS)
+            .map(|[x, y]| [x.clamp(0.0, 1.0), y.clamp(0.0, 1.0)])
+            .collect()
+    }
+}
+
+
+// Draws the strokes onto a copy of the slide, saved next to it as slide{id}.annotated.png
+async fn render(assets: PathBuf, slide: u16, strokes: Vec<protocol::Stroke>) -> Result<(), String> {
+
+    tokio::task::spawn_blocking(move || {
+
+        let mut image = image::open(assets.join(format!("slide{}.png", slide)))
+            .map_err(|e| e.to_string())?
+            .to_rgba8();
+
+        for stroke in &strokes {
+            draw_stroke(&mut image, stroke);
+        }
+
+        image.save(assets.join(manifest::annotated_file(slide))).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+
+// A round pen: a filled circle stamped every pixel along each segment
+fn draw_stroke(image: &mut RgbaImage, stroke: &protocol::Stroke) {
+
+    let color = parse_color(&stroke.color).unwrap_or(Rgba([255, 63, 0, 255]));
+
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    let radius = (stroke.width * width / 2.0).max(0.5);
+
+    let points: Vec<(f64, f64)> = stroke.points.iter().map(|[x, y]| (x * width, y * height)).collect();
+
+    // A single tap is a dot
+    if let [(x, y)] = points[..] {
+        stamp(image, x, y, radius, color);
+    }
+
+    for pair in points.windows(2) {
+
+        let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+
+        let steps = (x1 - x0).hypot(y1 - y0).ceil().max(1.0) as u32;
+
+        for step in 0..=steps {
+
+            let t = step as f64 / steps as f64;
+
+            stamp(image, x0 + (x1 - x0) * t, y0 + (y1 - y0) * t, radius, color);
+        }
+    }
+}
+
+
+fn stamp(image: &mut RgbaImage, cx: f64, cy: f64, radius: f64, color: Rgba<u8>) {
+
+    let min_x = (cx - radius).floor().max(0.0) as u32;
+    let min_y = (cy - radius).floor().max(0.0) as u32;
+    let max_x = ((cx + radius).ceil() as u32).min(image.width().saturating_sub(1));
+    let max_y = ((cy + radius).ceil() as u32).min(image.height().saturating_sub(1));
+
+    for y in min_y..=max_y {
+        for x in min_x..=max_x {
+
+            let (dx, dy) = (x as f64 - cx, y as f64 - cy);
+
+            if dx * dx + dy * dy <= radius * radius {
+                image.put_pixel(x, y, color);
+            }
+        }
+    }
+}
+
+
+// "#rrggbb", the only form the viewer sends
+fn parse_color(color: &str) -> Option<Rgba<u8>> {
+
+    let hex = color.strip_prefix('#').filter(|hex| hex.len() == 6 && hex.is_ascii())?;
+
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+
+    Some(Rgba([channel(0)?, channel(2)?, channel(4)?, 255]))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::AppState;
+    use std::sync::Arc;
+
+    fn stroke(color: &str, width: f64, points: Vec<[f64; 2]>) -> protocol::Stroke {
+        clamped_stroke(1, color, width, points)
+    }
+
+    #[test]
+    fn colors_are_six_digit_hex() {
+        assert_eq!(parse_color("#ff3f00"), Some(Rgba([255, 63, 0, 255])));
+        assert_eq!(parse_color("#FFFFFF"), Some(Rgba([255, 255, 255, 255])));
+
+        assert_eq!(parse_color("ff3f00"), None);
+        assert_eq!(parse_color("#f30"), None);
+        assert_eq!(parse_color("#ff3f0000"), None);
+        assert_eq!(parse_color("#gg0000"), None);
+        assert_eq!(parse_color("#ff3f0é"), None);
+        assert_eq!(parse_color(""), None);
+    }
+
+    #[tokio::test]
+    async fn bad_strokes_are_refused() {
+        let state = Arc::new(AppState::new());
+
+        assert!(add_stroke(&state, 1, "#ff3f00".into(), 0.01, Vec::new()).await.is_err());
+        assert!(add_stroke(&state, 1, "red".into(), 0.01, vec![[0.5, 0.5]]).await.is_err());
+
+        // A valid stroke on a slide that isn't there
+        assert_eq!(add_stroke(&state, 1, "#ff3f00".into(), 0.01, vec![[0.5, 0.5]]).await.err(), Some("No such slide".to_string()));
+    }
+
+    #[test]
+    fn strokes_stay_on_the_slide() {
+        let stroke = stroke("#FF3F00", 5.0, vec![[-1.0, 0.5], [0.5, 2.0], [f64::INFINITY, f64::NEG_INFINITY]]);
+
+        assert_eq!(stroke.color, "#ff3f00");
+        assert_eq!(stroke.width, MAX_WIDTH);
+        assert_eq!(stroke.points, vec![[0.0, 0.5], [0.5, 1.0], [1.0, 0.0]]);
+
+        assert_eq!(clamped_stroke(1, "#000000", 0.0, vec![[0.5, 0.5]]).width, MIN_WIDTH);
+    }
+
+    #[test]
+    fn long_strokes_are_cut_off() {
+        let points = (0..MAX_POINTS * 2).map(|i| [i as f64 / (MAX_POINTS * 2) as f64, 0.5]).collect();
+
+        let stroke = stroke("#000000", 0.01, points);
+
+        assert_eq!(stroke.points.len(), MAX_POINTS);
+        assert_eq!(stroke.points[0], [0.0, 0.5]);
+    }
+
+    #[tokio::test]
+    async fn the_annotated_copy_stays_the_size_of_the_slide() {
+        let assets = std::env::temp_dir().join(format!("slideshow-annotations-{}", std::process::id()));
+        std::fs::create_dir_all(&assets).unwrap();
+
+        RgbaImage::from_pixel(200, 100, Rgba([255, 255, 255, 255])).save(assets.join("slide3.png")).unwrap();
+
+        // Thick strokes along every edge and through the corners, which would go past the image if they weren't cut
+        let strokes = vec![
+            stroke("#ff0000", MAX_WIDTH, vec![[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0], [0.0, 0.0]]),
+            stroke("#0000ff", MAX_WIDTH, vec![[1.0, 1.0]])
+        ];
+
+        render(assets.clone(), 3, strokes).await.unwrap();
+
+        let annotated = image::open(assets.join(manifest::annotated_file(3))).unwrap().to_rgba8();
+
+        assert_eq!(annotated.dimensions(), (200, 100));
+        assert_eq!(*annotated.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+        assert_eq!(*annotated.get_pixel(199, 99), Rgba([0, 0, 255, 255]));
+        assert_eq!(*annotated.get_pixel(100, 50), Rgba([255, 255, 255, 255]));
+
+        // The slide itself is left as it was
+        assert_eq!(*image::open(assets.join("slide3.png")).unwrap().to_rgba8().get_pixel(0, 0), Rgba([255, 255, 255, 255]));
+
+        std::fs::remove_dir_all(&assets).unwrap();
+    }
+}
@@ -0,0 +1,175 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::Ordering;
+
+use crate::manifest;
+use crate::protocol;
+use crate::search;
+use crate::utility;
+
+
+// The JSON endpoints for integrations and scripts that don't want to speak WebSocket.
+// They read the same state ws_handler sends in its slidesInfo message.
+pub fn api_routes() -> Router<utility::CurrentSlideState> {
+
+    Router::new()
+        .route("/api/session", get(get_session))
+        .route("/api/slides", get(get_slides))
+        .route("/api/slides/{id}", get(get_slide))
+        .route("/api/current", get(get_current))
+        .route("/api/search", get(search_slides))
+        .route("/api/schema", get(get_schema))
+        .route("/api/schema/client", get(get_client_schema))
+}
+
+
+#[derive(Serialize)]
+struct SessionInfo {
+    session_id: String,
+    started_at: u64,
+    num_slides: u16, // highest slide number handed out so far
+    deleted_slides: Vec<u16>,
+    live_slides: usize, // slides the audience can actually see
+    presenter_slide: u16, // where the presenter is, 0 before the first capture
+    tls: bool,
+    pin_required: bool
+}
+
+// A slide from the manifest along with where its image can be fetched
+#[derive(Serialize)]
+struct SlideInfo {
+    #[serde(flatten)]
+    slide: manifest::SlideEntry,
+    url: String,
+    thumbnail_url: Option<String>
+}
+
+// ?q=latency chart
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: Option<String>
+}
+
+#[derive(Serialize)]
+struct ApiError {
+    error: String
+}
+
+
+fn slide_info(mut slide: manifest::SlideEntry) -> SlideInfo {
+
+    // Speaker notes are the presenter's own until they publish them
+    if !slide.notes_published {
+        slide.notes.clear();
+    }
+
+    SlideInfo {
+        url: format!("/slides/{}", slide.id),
+        thumbnail_url: slide.thumbnail.as_ref().map(|_| format!("/slides/{}/thumbnail", slide.id)),
+        slide
+    }
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+
+    (status, Json(ApiError { error: message.to_string() })).into_response()
+}
+
+
+async fn get_session(State(current_state): State<utility::CurrentSlideState>) -> Response {
+
+    let num_slides = *current_state.slide_number.lock().await;
+    let deleted_slides = current_state.deleted_slides.lock().await.clone();
+    let manifest = current_state.manifest.lock().await.clone();
+
+    Json(SessionInfo {
+        session_id: manifest.session_id,
+        started_at: manifest.started_at,
+        num_slides,
+        live_slides: manifest.slides.iter().filter(|s| s.deleted_at.is_none()).count(),
+        deleted_slides,
+        presenter_slide: *current_state.volatile_slide_number.lock().await,
+        tls: current_state.use_tls.load(Ordering::SeqCst),
+        pin_required: current_state.session_pin.lock().await.is_some()
+    }).into_response()
+}
+
+
+async fn get_slides(State(current_state): State<utility::CurrentSlideState>) -> Response {
+
+    let manifest = current_state.manifest.lock().await.clone();
+
+    let slides: Vec<SlideInfo> = manifest.slides.into_iter()
+        .filter(|slide| slide.deleted_at.is_none())
+        .map(slide_info)
+        .collect();
+
+    Json(slides).into_response()
+}
+
+
+async fn get_slide(
+    State(current_state): State<utility::CurrentSlideState>,
+    Path(id): Path<u16>
+) -> Response {
+
+    let slide = current_state.manifest.lock().await.slide(id).cloned();
+
+    match slide {
+
+        Some(slide) if slide.deleted_at.is_some() => error_response(StatusCode::GONE, "This slide was deleted"),
+
+        Some(slide) => Json(slide_info(slide)).into_response(),
+
+        None => error_response(StatusCode::NOT_FOUND, "No such slide")
+    }
+}
+
+
+// The slide the audience is shown in live mode, i.e. the latest one that wasn't deleted
+async fn get_current(State(current_state): State<utility::CurrentSlideState>) -> Response {
+
+    let manifest = current_state.manifest.lock().await.clone();
+
+    match manifest.slides.into_iter().rev().find(|slide| slide.deleted_at.is_none()) {
+
+        Some(slide) => Json(slide_info(slide)).into_response(),
+
+        None => error_response(StatusCode::NOT_FOUND, "No slides have been captured yet")
+    }
+}
+
+
+// The current session only, and only what the audience could see: the slides' text and published notes.
+// Past sessions are searched from the presenter's search window
+async fn search_slides(
+    State(current_state): State<utility::CurrentSlideState>,
+    Query(query): Query<SearchQuery>
+) -> Response {
+
+    match query.q.as_deref().map(str::trim) {
+
+        Some(q) if !q.is_empty() => Json(search::search(&current_state, q, search::Searcher::Audience).await).into_response(),
+
+        _ => error_response(StatusCode::BAD_REQUEST, "Missing the search terms (?q=)")
+    }
+}
+
+
+// The JSON Schema of every WebSocket message, generated from the Rust types
+async fn get_schema() -> Response {
+
+    Json(protocol::schema()).into_response()
+}
+
+async fn get_client_schema() -> Response {
+
+    Json(protocol::client_schema()).into_response()
+}
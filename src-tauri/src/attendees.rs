@@ -0,0 +1,441 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header::{CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_TYPE, SET_COOKIE}},
+    response::{IntoResponse, Response},
+    routing::{get, put},
+    Json, Router,
+};
+
+use base64::Engine;
+use headers::{Cookie, HeaderMapExt};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::manifest;
+use crate::pdf;
+use crate::utility;
+
+
+// Tells attendees apart across refreshes and phone locks. Finds their own notes, and keeps their votes to one each.
+// The value is "{id}.{signature}", signed with the app's secret like the access cookie, so nobody can make up an ID
+pub const ATTENDEE_COOKIE: &str = "slideshow_attendee";
+
+// Longest note we keep per slide, anything after that is cut off
+const MAX_NOTE_LENGTH: usize = 5000;
+
+
+// One attendee's bookmarks and notes, saved as assets/attendees/{attendee id}.json
+#[derive(Serialize, Deserialize, Default)]
+pub struct AttendeeNotes {
+    pub bookmarks: BTreeSet<u16>,
+    pub notes: BTreeMap<u16, String>
+}
+
+// The whole state of one slide for the attendee, so a save never has to merge anything
+#[derive(Deserialize)]
+struct SlideUpdate {
+    bookmarked: bool,
+    note: String
+}
+
+// A slide in the export
+struct ExportedSlide {
+    id: u16,
+    annotated: bool, // the presenter drew on it, so the copy with the ink is used
+    bookmarked: bool,
+    note: String
+}
+
+// ?format=markdown (a single file with the slides embedded) or ?format=pdf
+#[derive(Deserialize)]
+struct ExportQuery {
+    format: Option<String>
+}
+
+
+// Everything under /api/me is about the attendee holding the cookie, and the cookie is handed out on first use
+pub fn attendee_routes() -> Router<utility::CurrentSlideState> {
+
+    Router::new()
+        .route("/api/me", get(get_mine))
+        .route("/api/me/slides/{id}", put(update_slide))
+        .route("/api/me/export", get(export))
+}
+
+
+fn attendees_dir() -> PathBuf {
+
+    PathBuf::from("assets").join("attendees")
+}
+
+
+fn attendee_mac(secret: &[u8], id: &str) -> Hmac<Sha256> {
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+
+    // Keeps an attendee signature from ever passing for an access token, and the other way around
+    mac.update(b"attendee:");
+    mac.update(id.as_bytes());
+
+    mac
+}
+
+
+// The cookie value for an ID
+fn signed_id(secret: &[u8], id: &str) -> String {
+
+    format!("{}.{}", id, hex::encode(attendee_mac(secret, id).finalize().into_bytes()))
+}
+
+
+// The attendee's ID from the cookie, if it's one we handed out. Anything unsigned or tampered with is ignored
+pub fn attendee_id(secret: &[u8], headers: &HeaderMap) -> Option<String> {
+
+    let cookie = headers.typed_get::<Cookie>()?;
+    let (id, signature) = cookie.get(ATTENDEE_COOKIE)?.split_once('.')?;
+
+    // It becomes a file name, so nothing but our own hex IDs gets through
+    if id.len() != 32 || !id.bytes().all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase()) {
+        return None;
+    }
+
+    let signature = hex::decode(signature).ok()?;
+
+    // verify_slice compares in constant time
+    attendee_mac(secret, id).verify_slice(&signature).ok()?;
+
+    Some(id.to_string())
+}
+
+
+// The attendee's ID, and the Set-Cookie value when they didn't have one yet
+pub fn identify(current_state: &utility::CurrentSlideState, headers: &HeaderMap) -> (String, Option<String>) {
+
+    if let Some(id) = attendee_id(&current_state.access_secret, headers) {
+        return (id, None);
+    }
+
+    let id = hex::encode(rand::thread_rng().gen::<[u8; 16]>());
+
+    let cookie = format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age=604800{}",
+        ATTENDEE_COOKIE,
+        signed_id(&current_state.access_secret, &id),
+        if current_state.use_tls.load(Ordering::SeqCst) { "; Secure" } else { "" }
+    );
+
+    (id, Some(cookie))
+}
+
+
+// Who a connection counts as for upvotes, poll votes and the open question limit.
+// The attendee cookie survives reconnects and new tabs, a browser without one is known by its address.
+// It's a hash of either, since the attendee ID is what opens their notes and has no business in vote tallies
+pub fn voter_id(secret: &[u8], headers: &HeaderMap, peer: SocketAddr) -> String {
+
+    let who = match attendee_id(secret, headers) {
+        Some(id) => id,
+        None => peer.ip().to_string()
+    };
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(b"voter:");
+    mac.update(who.as_bytes());
+
+    hex::encode(mac.finalize().into_bytes())
+}
+
+
+async fn load(id: &str) -> AttendeeNotes {
+
+    tokio::fs::read_to_string(attendees_dir().join(format!("{}.json", id)))
+        .await
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+
+async fn save(id: &str, notes: &AttendeeNotes) -> std::io::Result<()> {
+
+    tokio::fs::create_dir_all(attendees_dir()).await?;
+
+    let json = serde_json::to_string_pretty(notes)?;
+
+    tokio::fs::write(attendees_dir().join(format!("{}.json", id)), json).await
+}
+
+
+// Adds the cookie to the response when it was just handed out
+pub fn with_cookie(cookie: Option<String>, response: Response) -> Response {
+
+    match cookie {
+        Some(cookie) => ([(SET_COOKIE, cookie)], response).into_response(),
+        None => response
+    }
+}
+
+
+async fn get_mine(
+    State(current_state): State<utility::CurrentSlideState>,
+    headers: HeaderMap
+) -> Response {
+
+    let (id, cookie) = identify(&current_state, &headers);
+
+    with_cookie(cookie, Json(load(&id).await).into_response())
+}
+
+
+async fn update_slide(
+    State(current_state): State<utility::CurrentSlideState>,
+    Path(slide): Path<u16>,
+    headers: HeaderMap,
+    Json(update): Json<SlideUpdate>
+) -> Response {
+
+    let exists = current_state.manifest.lock().await
+        .slide(slide)
+        .is_some_and(|entry| entry.deleted_at.is_none());
+
+    if !exists {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let (id, cookie) = identify(&current_state, &headers);
+
+    // One save at a time, so two quick edits can't undo each other
+    let _saving = current_state.attendee_lock.lock().await;
+
+    let mut notes = load(&id).await;
+
+    if update.bookmarked {
+        notes.bookmarks.insert(slide);
+    } else {
+        notes.bookmarks.remove(&slide);
+    }
+
+    let note: String = update.note.chars().take(MAX_NOTE_LENGTH).collect();
+
+    if note.trim().is_empty() {
+        notes.notes.remove(&slide);
+    } else {
+        notes.notes.insert(slide, note);
+    }
+
+    if let Err(e) = save(&id, &notes).await {
+        eprintln!("Failed to save the notes of attendee {}: {}", id, e);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    with_cookie(cookie, Json(notes).into_response())
+}
+
+
+// The slides the attendee bookmarked or wrote something on, in order, with their note
+async fn export(
+    State(current_state): State<utility::CurrentSlideState>,
+    Query(query): Query<ExportQuery>,
+    headers: HeaderMap
+) -> Response {
+
+    let Some(id) = attendee_id(&current_state.access_secret, &headers) else {
+        return (StatusCode::NOT_FOUND, "You haven't taken any notes yet").into_response();
+    };
+
+    let notes = load(&id).await;
+    let manifest = current_state.manifest.lock().await.clone();
+
+    let slides: Vec<ExportedSlide> = manifest.slides.iter()
+        .filter(|slide| slide.deleted_at.is_none())
+        .filter(|slide| notes.bookmarks.contains(&slide.id) || notes.notes.contains_key(&slide.id))
+        .map(|slide| ExportedSlide {
+            id: slide.id,
+            annotated: !slide.annotations.is_empty(),
+            bookmarked: notes.bookmarks.contains(&slide.id),
+            note: notes.notes.get(&slide.id).cloned().unwrap_or_default()
+        })
+        .collect();
+
+    match query.format.as_deref() {
+
+        Some("pdf") => {
+
+            let mut pages = Vec::new();
+            for slide in &slides {
+                pages.push(pdf::ExportedPage {
+                    heading: format!("Slide {}{}", slide.id, if slide.bookmarked { " (bookmarked)" } else { "" }),
+                    png: slide_image(slide).await,
+                    note: slide.note.clone()
+                });
+            }
+
+            let subtitle = format!("From {}", manifest.session_id);
+
+            // Compressing the slide images takes a moment, so it's kept off the async workers
+            match tokio::task::spawn_blocking(move || pdf::notes_document("My notes", &subtitle, &pages)).await {
+
+                Ok(document) => (
+                    [
+                        (CONTENT_TYPE, "application/pdf"),
+                        (CONTENT_DISPOSITION, "attachment; filename=\"slideshow-notes.pdf\""),
+                        (CACHE_CONTROL, "no-store")
+                    ],
+                    document
+                ).into_response(),
+
+                Err(e) => {
+                    eprintln!("Failed to make the PDF of attendee {}: {}", id, e);
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            }
+        }
+
+        _ => {
+            (
+                [
+                    (CONTENT_TYPE, "text/markdown; charset=utf-8"),
+                    (CONTENT_DISPOSITION, "attachment; filename=\"slideshow-notes.md\""),
+                    (CACHE_CONTROL, "no-store")
+                ],
+                markdown(&manifest.session_id, &slides).await
+            ).into_response()
+        }
+    }
+}
+
+
+// One self-contained file: the slides are embedded as data URIs, so it still works after the talk
+async fn markdown(session_id: &str, slides: &[ExportedSlide]) -> String {
+
+    let mut output = format!("# My notes\n\nFrom {}\n", session_id);
+
+    for slide in slides {
+
+        output.push_str(&format!("\n## Slide {}{}\n\n", slide.id, if slide.bookmarked { " ★" } else { "" }));
+
+        if let Some(png) = slide_image(slide).await {
+            output.push_str(&format!(
+                "![Slide {}](data:image/png;base64,{})\n",
+                slide.id,
+                base64::engine::general_purpose::STANDARD.encode(png)
+            ));
+        }
+
+        if !slide.note.is_empty() {
+            output.push_str(&format!("\n{}\n", escape_markdown(&slide.note)));
+        }
+    }
+
+    output
+}
+
+
+// The copy with the presenter's ink when there is one
+async fn slide_image(slide: &ExportedSlide) -> Option<Vec<u8>> {
+
+    let file = if slide.annotated { manifest::annotated_file(slide.id) } else { format!("slide{}.png", slide.id) };
+
+    match tokio::fs::read(PathBuf::from("assets").join(file)).await {
+        Ok(png) => Some(png),
+        Err(e) => {
+            eprintln!("Failed to read slide {} for the notes export: {}", slide.id, e);
+            None
+        }
+    }
+}
+
+
+// Notes are plain text. Every punctuation character is escaped, so a note can't turn into headings, links, images or HTML,
+// and its lines are kept as they were typed (without leading spaces, which would make a code block)
+fn escape_markdown(text: &str) -> String {
+
+    text.lines()
+        .map(|line| {
+            line.trim_start()
+                .chars()
+                .fold(String::new(), |mut escaped, c| {
+                    if c.is_ascii_punctuation() {
+                        escaped.push('\\');
+                    }
+                    escaped.push(c);
+                    escaped
+                })
+        })
+        .collect::<Vec<_>>()
+        .join("  \n")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notes_stay_plain_text_in_markdown() {
+        let note = "# Not a heading\n![tracker](https://example.com/x.png) <script>alert(1)</script>\n    not code\n\nSecond paragraph";
+
+        assert_eq!(
+            escape_markdown(note),
+            "\\# Not a heading  \n\
+            \\!\\[tracker\\]\\(https\\:\\/\\/example\\.com\\/x\\.png\\) \\<script\\>alert\\(1\\)\\<\\/script\\>  \n\
+            not code  \n  \n\
+            Second paragraph"
+        );
+    }
+
+    fn with_cookie(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("cookie", format!("{}={}", ATTENDEE_COOKIE, value).parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn only_signed_attendee_ids_are_read_from_the_cookie() {
+        let secret = [7u8; 32];
+        let id = "0123456789abcdef0123456789abcdef";
+
+        assert_eq!(attendee_id(&secret, &with_cookie(&signed_id(&secret, id))), Some(id.to_string()));
+
+        // Made up, unsigned, signed with another secret, or signed for another ID
+        assert_eq!(attendee_id(&secret, &with_cookie(id)), None);
+        assert_eq!(attendee_id(&secret, &with_cookie(&format!("{}.", id))), None);
+        assert_eq!(attendee_id(&secret, &with_cookie(&signed_id(&[8u8; 32], id))), None);
+
+        let other = signed_id(&secret, "fedcba9876543210fedcba9876543210");
+        let (_, signature) = other.split_once('.').unwrap();
+        assert_eq!(attendee_id(&secret, &with_cookie(&format!("{}.{}", id, signature))), None);
+
+        // Signed or not, it has to be one of our IDs since it becomes a file name
+        assert_eq!(attendee_id(&secret, &with_cookie(&signed_id(&secret, "../../etc/passwd"))), None);
+        assert_eq!(attendee_id(&secret, &with_cookie(&signed_id(&secret, "0123"))), None);
+        assert_eq!(attendee_id(&secret, &HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn browsers_without_the_cookie_vote_by_address() {
+        let secret = [7u8; 32];
+        let peer: SocketAddr = "10.0.0.9:50000".parse().unwrap();
+        let id = "0123456789abcdef0123456789abcdef";
+
+        let by_address = voter_id(&secret, &HeaderMap::new(), peer);
+        let by_cookie = voter_id(&secret, &with_cookie(&signed_id(&secret, id)), peer);
+
+        // An unsigned cookie counts for nothing, the browser is known by its address
+        assert_eq!(voter_id(&secret, &with_cookie(id), peer), by_address);
+        assert_ne!(by_cookie, by_address);
+
+        // The same attendee from another address is still the same voter
+        assert_eq!(voter_id(&secret, &with_cookie(&signed_id(&secret, id)), "10.0.0.10:1".parse().unwrap()), by_cookie);
+
+        // Neither the attendee ID nor the address shows up in it
+        assert!(!by_cookie.contains(id) && !by_address.contains("10.0.0.9"));
+    }
+}
@@ -0,0 +1,315 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::Ordering;
+
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::manifest;
+use crate::protocol;
+use crate::polls;
+use crate::questions;
+use crate::reactions;
+use crate::utility;
+
+
+// Longest display name we keep, anything after that is cut off
+const MAX_DISPLAY_NAME: usize = 40;
+
+// How many past connections the dashboard can look back on
+const RECENT_DISCONNECTS: usize = 50;
+
+// Close codes (from the 4000-4999 range the WebSocket spec leaves to applications) sent when the presenter drops a client
+pub const CLOSE_DISCONNECTED: u16 = 4001;
+pub const CLOSE_BLOCKED: u16 = 4003;
+
+
+// Why the presenter is dropping a client, sent as the close frame
+pub struct Disconnect {
+    pub code: u16,
+    pub reason: &'static str
+}
+
+
+// Why a connection ended, kept so the presenter can tell a kicked client from a phone that went to sleep
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DisconnectReason {
+    ClientClosed, // the client closed the connection (or left the page)
+    ConnectionError, // the connection broke while reading from it
+    HeartbeatTimeout, // the client stopped answering pings
+    SendFailed, // a message couldn't be delivered in time
+    Kicked, // the presenter removed the client
+    Blocked, // the presenter blocked the client's address
+    ServerShutdown // the presenter stopped the server
+}
+
+impl From<&Disconnect> for DisconnectReason {
+
+    fn from(disconnect: &Disconnect) -> Self {
+
+        if disconnect.code == CLOSE_BLOCKED { DisconnectReason::Blocked } else { DisconnectReason::Kicked }
+    }
+}
+
+// A connection that ended, for the dashboard's "recently left" list
+#[derive(Serialize, Clone)]
+pub struct DisconnectRecord {
+    pub id: u64,
+    pub address: SocketAddr,
+    pub display_name: Option<String>,
+    pub connected_at: u64,
+    pub disconnected_at: u64,
+    pub reason: DisconnectReason
+}
+
+
+// Audience clients watch, controllers (opened with the controller key) can also change slides
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientRole {
+    Audience,
+    Controller
+}
+
+
+// What we know about one connected WebSocket client
+#[derive(Serialize, Clone)]
+pub struct ClientInfo {
+    pub id: u64,
+    pub address: SocketAddr,
+    pub user_agent: Option<String>,
+    pub role: ClientRole,
+    pub display_name: Option<String>,
+    pub current_slide: Option<u16>, // as reported by the client
+    pub connected_at: u64, // milliseconds since the epoch
+    pub last_seen: u64, // the last time the client sent us anything
+    #[serde(skip)]
+    pub voter: String, // the same for every connection of one attendee, see attendees::voter_id
+    #[serde(skip)]
+    pub reaction_limit: reactions::RateLimit, // keeps one client from flooding everyone with reactions
+    #[serde(skip)]
+    pub disconnect_tx: mpsc::UnboundedSender<Disconnect> // tells the connection's handler to close the socket
+}
+
+
+// Hands out the next client ID and starts tracking the connection.
+// The receiver yields when the presenter wants this client gone
+pub async fn register(
+    current_state: &utility::CurrentSlideState,
+    address: SocketAddr,
+    user_agent: Option<String>,
+    voter: String,
+    role: ClientRole
+) -> (u64, mpsc::UnboundedReceiver<Disconnect>) {
+
+    let id = current_state.next_client_id.fetch_add(1, Ordering::SeqCst);
+    let now = manifest::now_millis();
+
+    let (disconnect_tx, disconnect_rx) = mpsc::unbounded_channel();
+
+    current_state.clients.lock().await.insert(id, ClientInfo {
+        id,
+        address,
+        user_agent,
+        role,
+        display_name: None,
+        current_slide: None,
+        connected_at: now,
+        last_seen: now,
+        voter,
+        reaction_limit: reactions::RateLimit::default(),
+        disconnect_tx
+    });
+
+    current_state.clients_notify.notify_one();
+
+    (id, disconnect_rx)
+}
+
+
+// Asks the client's connection to close with the given reason. Returns false if no such client is connected
+pub async fn disconnect(current_state: &utility::CurrentSlideState, client_id: u64, code: u16, reason: &'static str) -> bool {
+
+    match current_state.clients.lock().await.get(&client_id) {
+
+        Some(client) => client.disconnect_tx.send(Disconnect { code, reason }).is_ok(),
+
+        None => false
+    }
+}
+
+
+// Closes every remote control connection, e.g. after the presenter replaced the controller key
+pub async fn disconnect_controllers(current_state: &utility::CurrentSlideState, reason: &'static str) {
+
+    for client in current_state.clients.lock().await.values() {
+
+        if client.role == ClientRole::Controller {
+            let _ = client.disconnect_tx.send(Disconnect { code: CLOSE_DISCONNECTED, reason });
+        }
+    }
+}
+
+
+// Drops every client connected from the address, returns how many there were
+pub async fn disconnect_address(current_state: &utility::CurrentSlideState, ip: IpAddr, code: u16, reason: &'static str) -> usize {
+
+    let mut dropped = 0;
+
+    for client in current_state.clients.lock().await.values() {
+
+        if client.address.ip() == ip && client.disconnect_tx.send(Disconnect { code, reason }).is_ok() {
+            dropped += 1;
+        }
+    }
+
+    dropped
+}
+
+
+// Stops tracking the connection and remembers why it ended
+pub async fn unregister(current_state: &utility::CurrentSlideState, client_id: u64, reason: DisconnectReason) {
+
+    let Some(client) = current_state.clients.lock().await.remove(&client_id) else {
+        return;
+    };
+
+    println!("Client {} ({}) disconnected: {:?}", client_id, client.address, reason);
+
+    let mut recent = current_state.recent_disconnects.lock().await;
+
+    recent.push_front(DisconnectRecord {
+        id: client.id,
+        address: client.address,
+        display_name: client.display_name,
+        connected_at: client.connected_at,
+        disconnected_at: manifest::now_millis(),
+        reason
+    });
+
+    recent.truncate(RECENT_DISCONNECTS);
+
+    current_state.clients_notify.notify_one();
+}
+
+
+// Every connected client, oldest connection first
+pub async fn list(current_state: &utility::CurrentSlideState) -> Vec<ClientInfo> {
+
+    let mut clients: Vec<ClientInfo> = current_state.clients.lock().await.values().cloned().collect();
+
+    clients.sort_by_key(|client| client.id);
+
+    clients
+}
+
+
+// The connections that ended most recently, newest first
+pub async fn recent_disconnects(current_state: &utility::CurrentSlideState) -> Vec<DisconnectRecord> {
+
+    current_state.recent_disconnects.lock().await.iter().cloned().collect()
+}
+
+
+// Applies a message from a client. Returns the JSON reply for that client, if the message needs one
+pub async fn handle_message(
+    current_state: &utility::CurrentSlideState,
+    client_id: u64,
+    message: protocol::ClientMessage
+) -> Option<String> {
+
+    // Lock the clients only for the bookkeeping, the snapshot below needs other locks
+    let role = {
+        let mut clients = current_state.clients.lock().await;
+
+        let client = clients.get_mut(&client_id)?;
+
+        client.last_seen = manifest::now_millis();
+
+        match &message {
+
+            protocol::ClientMessage::SetDisplayName { name } => {
+
+                let name: String = name.trim().chars().take(MAX_DISPLAY_NAME).collect();
+
+                client.display_name = if name.is_empty() { None } else { Some(name) };
+            }
+
+            protocol::ClientMessage::ReportCurrentSlide { slide } => {
+
+                client.current_slide = Some(*slide);
+            }
+
+            _ => {}
+        }
+
+        client.role
+    };
+
+    // Names and positions show up on the presenter's dashboard
+    if matches!(message, protocol::ClientMessage::SetDisplayName { .. } | protocol::ClientMessage::ReportCurrentSlide { .. }) {
+        current_state.clients_notify.notify_one();
+    }
+
+    match message {
+
+        protocol::ClientMessage::Ping => {
+
+            let pong = protocol::ServerMessage::Pong(protocol::Pong { server_time: manifest::now_millis() });
+
+            Some(protocol::Envelope::direct(pong).to_json())
+        }
+
+        protocol::ClientMessage::RequestSnapshot => Some(current_state.snapshot().await.json),
+
+        protocol::ClientMessage::AskQuestion { text, slide } => {
+
+            // Everyone (the asker included) gets the question as a broadcast, only a refusal is a reply
+            let reason = questions::ask(current_state, client_id, &text, slide).await.err()?;
+
+            let rejected = protocol::ServerMessage::QuestionRejected(protocol::QuestionRejected { reason: reason.to_string() });
+
+            Some(protocol::Envelope::direct(rejected).to_json())
+        }
+
+        protocol::ClientMessage::UpvoteQuestion { question_id } => {
+
+            questions::upvote(current_state, client_id, question_id).await;
+
+            None
+        }
+
+        protocol::ClientMessage::Vote { poll_id, option } => {
+
+            polls::vote(current_state, client_id, poll_id, option).await;
+
+            None
+        }
+
+        protocol::ClientMessage::React { reaction, slide } => {
+
+            reactions::react(current_state, client_id, reaction, slide).await;
+
+            None
+        }
+
+        // Handed to the key press worker, so a tap on the phone does exactly what the arrow key would
+        protocol::ClientMessage::Control { action } if role == ClientRole::Controller => {
+
+            if let Some(control_tx) = current_state.control_tx.get() {
+                let _ = control_tx.send(action);
+            }
+
+            None
+        }
+
+        protocol::ClientMessage::Control { .. } => {
+
+            eprintln!("Ignoring a control message from client {}, it didn't connect as a controller", client_id);
+
+            None
+        }
+
+        _ => None
+    }
+}
@@ -1,12 +1,26 @@
 use local_ip_address::local_ip;
 use crate::server;
 use crate::utility;
+use crate::manifest;
+use crate::protocol;
+use crate::clients;
+use crate::access;
+use crate::pointer;
+use crate::annotations;
+use crate::questions;
+use crate::polls;
+use crate::reactions;
+use crate::notes;
+use crate::ocr;
+use crate::search;
 use tokio::runtime;
 use tauri::{Emitter, State};
 
 use tauri::{AppHandle, Manager};
 
 use std::path::PathBuf;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::Ordering;
 
 
 use local_ip_address::list_afinet_netifas;
@@ -28,19 +42,322 @@ pub fn is_connected() -> bool {
 
 // The function to get our WebSocket and HTTPS server up and running
 #[tauri::command]
-pub fn start_server(
+pub async fn start_server(
     app_handle: tauri::AppHandle,
+    use_tls: bool,
+    require_pin: bool,
     current_state: tauri::State<'_, utility::CurrentSlideState>
-) {
+) -> Result<(), String> {
+
+    // Remembered so a restart comes back up in the same mode
+    current_state.use_tls.store(use_tls, Ordering::SeqCst);
+    current_state.require_pin.store(require_pin, Ordering::SeqCst);
+
+    launch_server(app_handle, current_state.inner().clone()).await
+}
+
+
+
+// Everyone currently connected over the WebSocket, for the presenter's dashboard
+#[tauri::command]
+pub async fn list_clients(
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<Vec<clients::ClientInfo>, String> {
+
+    Ok(clients::list(current_state.inner()).await)
+}
+
+
+
+// The connections that ended recently and why, for the dashboard
+#[tauri::command]
+pub async fn list_recent_disconnects(
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<Vec<clients::DisconnectRecord>, String> {
+
+    Ok(clients::recent_disconnects(current_state.inner()).await)
+}
+
+
+
+// Drops a single client, it can connect again (unless its address gets blocked)
+#[tauri::command]
+pub async fn disconnect_client(
+    client_id: u64,
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<(), String> {
+
+    if clients::disconnect(current_state.inner(), client_id, clients::CLOSE_DISCONNECTED, "Removed by the presenter").await {
+        Ok(())
+    } else {
+        Err("That client is no longer connected".into())
+    }
+}
+
+
+
+// Drops every client from the address and refuses its HTTP and WebSocket requests for the rest of the session
+#[tauri::command]
+pub async fn block_address(
+    address: String,
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<String, String> {
+
+    // The dashboard shows ip:port, but a bare ip works as well
+    let ip: IpAddr = match address.parse::<SocketAddr>() {
+        Ok(socket_address) => socket_address.ip(),
+        Err(_) => address.parse().map_err(|_| "That is not a valid address".to_string())?
+    };
+
+    if access::is_trusted_peer(current_state.inner(), SocketAddr::new(ip, 0)).await {
+        return Err("The presenter's own machine can't be blocked".into());
+    }
+
+    current_state.blocked_addresses.lock().await.insert(ip);
+
+    let dropped = clients::disconnect_address(current_state.inner(), ip, clients::CLOSE_BLOCKED, "Blocked by the presenter").await;
+
+    Ok(format!("Blocked {} and disconnected {} clients", ip, dropped))
+}
+
+
+
+// Turns the laser pointer relay on or off. It takes effect right away, no restart needed
+#[tauri::command]
+pub async fn set_pointer_relay(
+    enabled: bool,
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<(), String> {
+
+    current_state.pointer_relay.store(enabled, Ordering::SeqCst);
+
+    if !enabled {
+
+        // Don't leave a pointer frozen on everyone's screen
+        let mut tracker = current_state.pointer.lock().await;
+
+        pointer::hide(current_state.inner(), &mut tracker);
+    }
+
+    Ok(())
+}
+
+
+
+// Stores a pen stroke drawn in the slide viewer and shows it to the audience
+#[tauri::command]
+pub async fn save_annotation(
+    slide: u16,
+    color: String,
+    width: f64,
+    points: Vec<[f64; 2]>,
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<protocol::Stroke, String> {
+
+    annotations::add_stroke(current_state.inner(), slide, color, width, points).await
+}
+
+
+
+#[tauri::command]
+pub async fn get_annotations(
+    slide: u16,
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<Vec<protocol::Stroke>, String> {
+
+    annotations::strokes(current_state.inner(), slide).await
+}
+
+
+
+// The Q&A queue, sorted the way the presenter should go through it
+#[tauri::command]
+pub async fn list_questions(
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<Vec<questions::Question>, String> {
+
+    Ok(questions::sorted(current_state.inner()).await)
+}
+
+
+
+// Marks a question answered, hides it from the audience, or opens it again
+#[tauri::command]
+pub async fn set_question_status(
+    question_id: u64,
+    status: questions::QuestionStatus,
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<(), String> {
+
+    questions::set_status(current_state.inner(), question_id, status).await
+}
+
+
+
+// Polls prepared ahead of the talk are kept in the app data folder, outside any session
+fn poll_drafts_dir(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+
+    app.path().app_data_dir().map_err(|e| e.to_string())
+}
+
+
+
+#[tauri::command]
+pub async fn list_poll_drafts(app: tauri::AppHandle) -> Result<Vec<polls::PollDraft>, String> {
+
+    Ok(polls::load_drafts(&poll_drafts_dir(&app)?))
+}
+
+
+
+// Saves a new draft (id 0) or updates an existing one
+#[tauri::command]
+pub async fn save_poll_draft(
+    app: tauri::AppHandle,
+    id: u64,
+    question: String,
+    options: Vec<String>
+) -> Result<polls::PollDraft, String> {
+
+    polls::save_draft(&poll_drafts_dir(&app)?, &question, &options, id)
+}
+
+
+
+#[tauri::command]
+pub async fn delete_poll_draft(app: tauri::AppHandle, id: u64) -> Result<(), String> {
+
+    polls::delete_draft(&poll_drafts_dir(&app)?, id)
+}
+
+
+
+// Puts a question to the audience, closing whatever poll was still open
+#[tauri::command]
+pub async fn open_poll(
+    question: String,
+    options: Vec<String>,
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<u64, String> {
+
+    polls::open(current_state.inner(), &question, &options).await
+}
+
+
+
+#[tauri::command]
+pub async fn close_poll(
+    poll_id: u64,
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<(), String> {
+
+    polls::close(current_state.inner(), poll_id).await
+}
+
+
+
+// Shows the results to the audience
+#[tauri::command]
+pub async fn publish_poll_results(
+    poll_id: u64,
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<(), String> {
+
+    polls::publish(current_state.inner(), poll_id).await
+}
+
+
+
+// The polls of this session with their live results, newest first
+#[tauri::command]
+pub async fn list_polls(
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<Vec<polls::Poll>, String> {
+
+    Ok(polls::list(current_state.inner()).await)
+}
+
+
+
+#[tauri::command]
+pub async fn clear_annotations(
+    slide: u16,
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<(), String> {
+
+    annotations::clear(current_state.inner(), slide).await
+}
+
+
+
+// Shuts both servers down, closing every WebSocket client with a close frame
+#[tauri::command]
+pub async fn stop_server(
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<(), String> {
+
+    halt_server(current_state.inner().clone()).await
+}
+
+
+
+// Stops the servers and brings them back up on fresh ports, keeping the slides captured so far
+#[tauri::command]
+pub async fn restart_server(
+    app_handle: tauri::AppHandle,
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<(), String> {
 
     let extracted_state = current_state.inner().clone();
 
-    // Spawn a new worker thread for the server
-    tauri::async_runtime::spawn(async move {
+    halt_server(extracted_state.clone()).await?;
+
+    launch_server(app_handle, extracted_state).await
+}
+
+
+async fn launch_server(app_handle: tauri::AppHandle, current_state: utility::CurrentSlideState) -> Result<(), String> {
+
+    let mut task_lock = current_state.server_task.lock().await;
+
+    // The task finishes on its own when the server fails to start, so only a live one counts
+    if let Some(task) = task_lock.as_ref() {
+
+        if !task.is_finished() {
+            return Err("The server is already running".into());
+        }
+    }
+
+    // Lower the flag left behind by the previous stop
+    current_state.shutdown_tx.send_replace(false);
+
+    let state_clone = current_state.clone();
+
+    // Spawn a new worker for the server
+    *task_lock = Some(tokio::spawn(async move {
         // Since setup_server is likely async, we just await it
-        server::setup_server(app_handle, extracted_state).await;
-    });
+        server::setup_server(app_handle, state_clone).await;
+    }));
 
+    Ok(())
+}
+
+
+async fn halt_server(current_state: utility::CurrentSlideState) -> Result<(), String> {
+
+    // Keep the lock for the whole shutdown so a start can't sneak in halfway through
+    let mut task_lock = current_state.server_task.lock().await;
+
+    let task = match task_lock.take() {
+
+        Some(t) => t,
+
+        None => return Err("The server is not running".into())
+    };
+
+    current_state.shutdown_tx.send_replace(true);
+
+    task.await.map_err(|_| "The server task ended abnormally".to_string())
 }
 
 
@@ -74,12 +391,215 @@ pub async fn open_slide_viewer(app: tauri::AppHandle, server_address: String, sl
 
 
 
+// The audience's reactions so far, by slide
+#[tauri::command]
+pub async fn get_reaction_totals(
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<reactions::ReactionCounts, String> {
+
+    Ok(reactions::totals(current_state.inner()).await)
+}
+
+
+
+#[tauri::command]
+pub async fn get_slide_notes(
+    slide: u16,
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<notes::SlideNotes, String> {
+
+    notes::get(current_state.inner(), slide).await
+}
+
+
+
+// What OCR read on the slide, for the slide viewer
+#[tauri::command]
+pub async fn get_slide_text(
+    slide: u16,
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<ocr::SlideText, String> {
+
+    ocr::slide_text(current_state.inner(), slide).await
+}
+
+
+// Looks through every session we captured, speaker notes included
 #[tauri::command]
-pub async fn show_qr_code(app: tauri::AppHandle, server_address: String) {
+pub async fn search_slides(
+    query: String,
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<Vec<search::SearchResult>, String> {
+
+    Ok(search::search(current_state.inner(), &query, search::Searcher::Presenter).await)
+}
+
+
+#[tauri::command]
+pub async fn open_search_window(app: tauri::AppHandle) -> Result<(), String> {
+
+    // Only one, bring it to the front if it's already open
+    if let Some(window) = app.get_webview_window("search") {
+        return window.set_focus().map_err(|e| e.to_string());
+    }
+
+    let parent_window = app.get_webview_window("main").ok_or("Could not get the parent window")?;
+
+    tauri::WebviewWindowBuilder::new(&app, "search", tauri::WebviewUrl::App("search".into()))
+        .parent(&parent_window)
+        .map_err(|e| e.to_string())?
+        .title("Search slides")
+        .inner_size(640.0, 720.0)
+        .build()
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+
+
+// Speaker notes stay with the presenter unless they're published
+#[tauri::command]
+pub async fn set_slide_notes(
+    slide: u16,
+    notes: String,
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<(), String> {
+
+    notes::set(current_state.inner(), slide, &notes).await
+}
+
+
+
+// Shares a slide's notes with the audience as handout notes (or stops sharing them)
+#[tauri::command]
+pub async fn publish_slide_notes(
+    slide: u16,
+    published: bool,
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<(), String> {
+
+    notes::set_published(current_state.inner(), slide, published).await
+}
+
+
+
+// The window where the presenter prepares polls, runs them and watches the votes come in
+#[tauri::command]
+pub async fn open_polls_window(app: tauri::AppHandle) -> Result<(), String> {
+
+    // Only one, bring it to the front if it's already open
+    if let Some(window) = app.get_webview_window("polls") {
+        return window.set_focus().map_err(|e| e.to_string());
+    }
+
+    let parent_window = app.get_webview_window("main").ok_or("Could not get the parent window")?;
+
+    tauri::WebviewWindowBuilder::new(&app, "polls", tauri::WebviewUrl::App("polls".into()))
+        .parent(&parent_window)
+        .map_err(|e| e.to_string())?
+        .title("Polls")
+        .inner_size(640.0, 720.0)
+        .build()
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+
+
+// The presenter's own screen: the current slide with its speaker notes, following the arrow keys
+#[tauri::command]
+pub async fn open_presenter_view(app: tauri::AppHandle, server_address: String, slide_number: u32) {
+
+    let url_string = format!("presenter-view?slideNumber={}&serverAddress={}", slide_number, server_address);
+
+    let url = tauri::WebviewUrl::App(url_string.into());
+
+    let parent_window = match app.get_webview_window("main") {
+
+        Some(w) => w,
+
+        None => {
+            println!("Could not get the parent window!");
+            return;
+        }
+    };
+
+    tauri::WebviewWindowBuilder::new(&app, "presenter_view", url)
+        .parent(&parent_window)
+        .unwrap()
+        .title("Presenter View")
+        .inner_size(1000.0, 700.0)
+        .build()
+        .map(|_| ())
+        .map_err(|e| e.to_string());  
+}
+
+
+
+// Makes a new remote control link and drops any phone still using the old one, e.g. when a phone went missing
+#[tauri::command]
+pub async fn reset_controller_key(
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<String, String> {
+
+    let key = access::generate_controller_key();
+
+    *current_state.controller_key.lock().await = key.clone();
+
+    clients::disconnect_controllers(current_state.inner(), "The remote control link was replaced").await;
+
+    Ok(key)
+}
+
+
+
+// The QR code for the presenter's own phone, pointing at {server_url}/controller?key=...
+// Same window as the audience one, just without the address in big letters
+#[tauri::command]
+pub async fn show_controller_qr(
+    app: tauri::AppHandle,
+    server_url: String,
+    current_state: tauri::State<'_, utility::CurrentSlideState>
+) -> Result<(), String> {
+
+    let key = current_state.controller_key.lock().await.clone();
+
+    let url_string = format!("qr-code?serverUrl={}&mode=controller&key={}", server_url, key);
+
+    let url = tauri::WebviewUrl::App(url_string.into());
+
+    let parent_window = app.get_webview_window("main").ok_or("Could not get the parent window")?;
+
+    tauri::WebviewWindowBuilder::new(&app, "controller_qr", url)
+        .parent(&parent_window)
+        .map_err(|e| e.to_string())?
+        .title("Remote Control")
+        .inner_size(400.0, 520.0)
+        .resizable(false)
+        .fullscreen(false)
+        .build()
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+
+
+#[tauri::command]
+pub async fn show_qr_code(app: tauri::AppHandle, server_url: String, fingerprint: Option<String>, pin: Option<String>) {
 
     println!("Show QR Code function called!");
 
-    let url_string = format!("qr-code?serverAddress={}", server_address);
+    let mut url_string = format!("qr-code?serverUrl={}", server_url);
+
+    // In TLS mode, show the certificate fingerprint under the QR code so attendees can verify it
+    if let Some(fingerprint) = fingerprint {
+        url_string.push_str(&format!("&fingerprint={}", fingerprint));
+    }
+
+    // The QR window can embed the PIN so scanning joins in one step
+    if let Some(pin) = pin {
+        url_string.push_str(&format!("&pin={}", pin));
+    }
 
     let url = tauri::WebviewUrl::App(url_string.into());
 
@@ -97,7 +617,7 @@ pub async fn show_qr_code(app: tauri::AppHandle, server_address: String) {
         .parent(&parent_window)
         .unwrap()
         .title("QR Code")
-        .inner_size(400.0, 400.0)
+        .inner_size(400.0, 480.0)
         .resizable(false)
         .fullscreen(false)
         .build()
@@ -131,18 +651,28 @@ pub async fn delete_slide(
                 lock.clone() 
             }; 
 
-            let message_construct = utility::ServerMessage {
+            // Keep the slide in the manifest, just mark when it went away
+            {
+                let mut manifest = current_state.manifest.lock().await;
+
+                if let Some(slide) = manifest.slide_mut(slide_number) {
+                    slide.deleted_at = Some(manifest::now_millis());
+                }
 
-                message_type: "slideDeleted".to_string(),
-                current_state: utility::CurrentState {
-                    num_slides: None,
-                    deleted_slides: Some(deleted_list)
-                },
-            };
+                if let Err(e) = manifest.save().await {
+                    eprintln!("Failed to save the manifest: {}", e);
+                }
+
+                if let Some(slide) = manifest.slide(slide_number) {
+                    search::update_slide(current_state.inner(), &manifest.session_id, slide).await;
+                }
+            }
 
-            let json_string = serde_json::to_string(&message_construct).unwrap();
+            let message_construct = protocol::ServerMessage::SlideDeleted(protocol::SlideDeleted {
+                deleted_slides: deleted_list
+            });
 
-            match current_state.broadcast_tx.send(json_string) {
+            match current_state.broadcast(message_construct).await {
 
                 Ok(num) => Ok(format!("Notified {} clients", num)),
                 Err(_) => Err("Broadcast failed".into()),
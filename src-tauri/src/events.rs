@@ -0,0 +1,214 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::{
+    extract::{ConnectInfo, Query, State},
+    http::{HeaderMap, header::{CONTENT_TYPE, CACHE_CONTROL, USER_AGENT}},
+    response::{IntoResponse, Response},
+    response::sse::{Event, KeepAlive, Sse},
+    routing::get,
+    Router,
+};
+
+use futures_util::stream::{self, Stream};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::attendees;
+use crate::clients;
+use crate::protocol;
+use crate::server;
+use crate::utility;
+
+
+// How long a long-poll request waits for something new before answering with an empty list
+const POLL_TIMEOUT: Duration = Duration::from_secs(25);
+
+
+// The same messages as the WebSocket, for clients behind proxies that break the upgrade.
+// /events is a Server-Sent Events stream, /poll answers once there's something newer than ?since
+pub fn event_routes() -> Router<utility::CurrentSlideState> {
+
+    Router::new()
+        .route("/events", get(events_handler))
+        .route("/poll", get(poll_handler))
+}
+
+
+async fn events_handler(
+    State(current_state): State<utility::CurrentSlideState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Query(resume): Query<server::ResumeQuery>,
+    headers: HeaderMap
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+
+    // EventSource reconnects on its own and sends the id of the last event it got,
+    // which is more recent than the ?since the page opened the stream with
+    let since = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .or(resume.since);
+
+    let user_agent = headers
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+
+    let voter = attendees::voter_id(&current_state.access_secret, &headers, peer);
+
+    // The task writes the events, the response streams whatever it wrote.
+    // When the client goes away the receiver is dropped and the task stops
+    let (tx, mut rx) = mpsc::channel(16);
+
+    tauri::async_runtime::spawn(stream_events(current_state, peer, user_agent, voter, since, tx));
+
+    let stream = stream::poll_fn(move |cx| rx.poll_recv(cx).map(|event| event.map(Ok::<_, Infallible>)));
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+
+async fn stream_events(
+    current_state: utility::CurrentSlideState,
+    peer: SocketAddr,
+    user_agent: Option<String>,
+    voter: String,
+    since: Option<u64>,
+    tx: mpsc::Sender<Event>
+) {
+
+    let (mut rx, initial_messages) = current_state.subscribe(since).await;
+
+    // Everything up to this sequence number was already sent (by a snapshot or the replay)
+    let mut covered_seq = initial_messages.last().map(|m| m.seq).unwrap_or(0);
+
+    for message in initial_messages {
+
+        if tx.send(broadcast_event(message)).await.is_err() {
+            return;
+        }
+    }
+
+    // Clients on the stream show up in the dashboard like everyone else
+    let (client_id, mut disconnect_rx) = clients::register(&current_state, peer, user_agent, voter, clients::ClientRole::Audience).await;
+
+    let welcome = protocol::Envelope::direct(protocol::ServerMessage::Welcome(protocol::Welcome { client_id }));
+    let _ = tx.send(Event::default().data(welcome.to_json())).await;
+
+    let mut shutdown_rx = current_state.shutdown_tx.subscribe();
+
+    let reason = loop {
+
+        tokio::select! {
+
+            msg = rx.recv() => {
+
+                let event = match msg {
+
+                    Ok(msg) if msg.already_sent(covered_seq) => continue,
+
+                    Ok(msg) => broadcast_event(msg),
+
+                    // Missed something, so bring the client up to date with a fresh snapshot
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        eprintln!("Event stream client lagged behind by {} messages, sending a snapshot", skipped);
+
+                        let snapshot = current_state.snapshot().await;
+                        covered_seq = snapshot.seq;
+
+                        broadcast_event(snapshot)
+                    }
+
+                    Err(_) => break clients::DisconnectReason::ServerShutdown
+                };
+
+                if tx.send(event).await.is_err() {
+                    break clients::DisconnectReason::ClientClosed;
+                }
+            }
+
+            Some(disconnect) = disconnect_rx.recv() => {
+
+                // EventSource has no close frame, so the page gets a named event telling it not to reconnect
+                let data = serde_json::json!({ "code": disconnect.code, "reason": disconnect.reason });
+                let _ = tx.send(Event::default().event("disconnect").data(data.to_string())).await;
+
+                break clients::DisconnectReason::from(&disconnect);
+            }
+
+            // The client went away while nothing was happening
+            _ = tx.closed() => break clients::DisconnectReason::ClientClosed,
+
+            // Ending the stream lets the server finish its graceful shutdown
+            _ = server::wait_for_shutdown(&mut shutdown_rx) => break clients::DisconnectReason::ServerShutdown
+        }
+    };
+
+    clients::unregister(&current_state, client_id, reason).await;
+}
+
+
+// A broadcast as an SSE event, with the sequence number as its id so a reconnect resumes from there
+fn broadcast_event(message: utility::Broadcast) -> Event {
+
+    // Volatile ones have no number, and an id would reset the one EventSource resumes from
+    if message.volatile {
+        return Event::default().data(message.json);
+    }
+
+    Event::default().id(message.seq.to_string()).data(message.json)
+}
+
+
+// Long polling, for when even Server-Sent Events don't get through.
+// Answers right away with what the client missed (or a snapshot if it has nothing yet),
+// otherwise waits for the next broadcast. The response is always a JSON array of messages
+async fn poll_handler(
+    State(current_state): State<utility::CurrentSlideState>,
+    Query(resume): Query<server::ResumeQuery>
+) -> Response {
+
+    let (mut rx, mut messages) = current_state.subscribe(resume.since).await;
+
+    if messages.is_empty() {
+
+        let mut shutdown_rx = current_state.shutdown_tx.subscribe();
+
+        tokio::select! {
+
+            msg = rx.recv() => match msg {
+
+                Ok(msg) => {
+
+                    messages.push(msg);
+
+                    // Hand over anything else that is already waiting too
+                    while let Ok(msg) = rx.try_recv() {
+                        messages.push(msg);
+                    }
+                }
+
+                Err(broadcast::error::RecvError::Lagged(_)) => messages.push(current_state.snapshot().await),
+
+                Err(_) => {}
+            },
+
+            // Nothing happened, the client simply asks again
+            _ = tokio::time::sleep(POLL_TIMEOUT) => {}
+
+            _ = server::wait_for_shutdown(&mut shutdown_rx) => {}
+        }
+    }
+
+    // The messages are already serialized, so the array is put together by hand
+    let body = format!(
+        "[{}]",
+        messages.into_iter().map(|m| m.json).collect::<Vec<_>>().join(",")
+    );
+
+    (
+        [(CONTENT_TYPE, "application/json"), (CACHE_CONTROL, "no-store")],
+        body
+    ).into_response()
+}
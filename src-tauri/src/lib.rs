@@ -1,33 +1,69 @@
 mod commands;
 mod utility;
 mod server;
-
-use tokio::sync::{Mutex, broadcast};
+mod tls;
+mod access;
+mod api;
+mod manifest;
+mod protocol;
+mod clients;
+mod events;
+mod pointer;
+mod annotations;
+mod questions;
+mod polls;
+mod reactions;
+mod notes;
+mod attendees;
+mod ocr;
+mod search;
+mod pdf;
 
 use std::sync::Arc;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
 
-    let (tx, rx) = broadcast::channel(100); // Buffer size of 100 messages
-
-    drop(rx);
-
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
 
-        .manage(Arc::new(utility::AppState {
-            slide_number: Mutex::new(0),
-            broadcast_tx: tx,
-            deleted_slides: Mutex::new(Vec::new()),
-            volatile_slide_number: Mutex::new(0)
-        }))
+        .manage(Arc::new(utility::AppState::new()))
 
 
         .invoke_handler(tauri::generate_handler![
 
             commands::is_connected,
             commands::start_server,
+            commands::stop_server,
+            commands::restart_server,
+            commands::list_clients,
+            commands::list_recent_disconnects,
+            commands::disconnect_client,
+            commands::block_address,
+            commands::set_pointer_relay,
+            commands::save_annotation,
+            commands::get_annotations,
+            commands::clear_annotations,
+            commands::list_questions,
+            commands::set_question_status,
+            commands::list_poll_drafts,
+            commands::save_poll_draft,
+            commands::delete_poll_draft,
+            commands::open_poll,
+            commands::close_poll,
+            commands::publish_poll_results,
+            commands::list_polls,
+            commands::open_polls_window,
+            commands::get_reaction_totals,
+            commands::get_slide_notes,
+            commands::set_slide_notes,
+            commands::publish_slide_notes,
+            commands::open_presenter_view,
+            commands::reset_controller_key,
+            commands::get_slide_text,
+            commands::search_slides,
+            commands::open_search_window,
+            commands::show_controller_qr,
             commands::open_slide_viewer,
             commands::delete_slide,
             commands::show_qr_code
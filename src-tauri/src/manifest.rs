@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+
+use crate::ocr;
+use crate::protocol;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+
+// Everything we know about the current session, kept next to the slides as assets/manifest.json
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SessionManifest {
+    pub session_id: String,
+    pub started_at: u64, // milliseconds since the epoch
+    pub slides: Vec<SlideEntry>
+}
+
+// One captured slide. Deleted slides stay in the list with deleted_at set
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SlideEntry {
+    pub id: u16,
+    pub file: String,
+    pub captured_at: u64,
+    pub width: u32,
+    pub height: u32,
+    pub thumbnail: Option<String>, // the small preview next to the slide, if it could be made
+    // Added along the way, so manifests written before them still load
+    #[serde(default)]
+    pub annotations: Vec<protocol::Stroke>, // the presenter's ink, in the order it was drawn
+    #[serde(default)]
+    pub reactions: BTreeMap<protocol::Reaction, u64>, // how many of each reaction the audience sent on this slide
+    #[serde(default)]
+    pub notes: String, // the presenter's speaker notes, private unless published
+    #[serde(default)]
+    pub notes_published: bool, // whether the audience gets the notes as handout notes
+    #[serde(default)]
+    pub text: Option<String>, // what OCR read on the slide, once it has
+    #[serde(default)]
+    pub text_status: ocr::TextStatus,
+    pub deleted_at: Option<u64>
+}
+
+
+impl SessionManifest {
+
+    pub fn new() -> Self {
+
+        let started_at = now_millis();
+
+        SessionManifest {
+            session_id: format!("session-{}", started_at / 1000),
+            started_at,
+            slides: Vec::new()
+        }
+    }
+
+    pub fn slide(&self, id: u16) -> Option<&SlideEntry> {
+
+        self.slides.iter().find(|slide| slide.id == id)
+    }
+
+    pub fn slide_mut(&mut self, id: u16) -> Option<&mut SlideEntry> {
+
+        self.slides.iter_mut().find(|slide| slide.id == id)
+    }
+
+    // The notes the audience may see, by slide
+    pub fn published_notes(&self) -> BTreeMap<u16, String> {
+
+        self.slides.iter()
+            .filter(|slide| slide.deleted_at.is_none() && slide.notes_published && !slide.notes.is_empty())
+            .map(|slide| (slide.id, slide.notes.clone()))
+            .collect()
+    }
+
+    // What OCR read on the slides, by slide
+    pub fn slide_texts(&self) -> BTreeMap<u16, String> {
+
+        self.slides.iter()
+            .filter(|slide| slide.deleted_at.is_none())
+            .filter_map(|slide| slide.text.clone().map(|text| (slide.id, text)))
+            .collect()
+    }
+
+    // Writes the manifest to the assets folder, next to the slides it describes
+    pub async fn save(&self) -> std::io::Result<()> {
+
+        let json = serde_json::to_string_pretty(self)?;
+
+        tokio::fs::write(manifest_path(), json).await
+    }
+}
+
+
+pub fn manifest_path() -> PathBuf {
+
+    PathBuf::from("assets").join("manifest.json")
+}
+
+
+// The small preview saved next to slide{id}.png
+pub fn thumbnail_file(id: u16) -> String {
+
+    format!("slide{}.thumb.png", id)
+}
+
+
+// The copy of the slide with the presenter's ink drawn on it
+pub fn annotated_file(id: u16) -> String {
+
+    format!("slide{}.annotated.png", id)
+}
+
+
+pub fn now_millis() -> u64 {
+
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifests_from_before_the_later_fields_still_load() {
+        let json = r#"{
+            "session_id": "session-1",
+            "started_at": 1000,
+            "slides": [
+                { "id": 1, "file": "slide1.png", "captured_at": 1001, "width": 1920, "height": 1080, "thumbnail": null, "deleted_at": null }
+            ]
+        }"#;
+
+        let manifest: SessionManifest = serde_json::from_str(json).unwrap();
+        let slide = manifest.slide(1).unwrap();
+
+        assert!(slide.annotations.is_empty());
+        assert!(slide.reactions.is_empty());
+        assert_eq!(slide.notes, "");
+        assert!(!slide.notes_published);
+        assert_eq!(slide.text, None);
+        assert_eq!(slide.text_status, ocr::TextStatus::Pending);
+    }
+
+    #[test]
+    fn only_live_slides_share_notes_and_text() {
+        let mut manifest: SessionManifest = serde_json::from_str(r#"{ "session_id": "s", "started_at": 0, "slides": [] }"#).unwrap();
+
+        for id in 1..=3 {
+            manifest.slides.push(serde_json::from_value(serde_json::json!({
+                "id": id, "file": format!("slide{}.png", id), "captured_at": 0, "width": 1, "height": 1,
+                "notes": "Notes", "notes_published": id != 2, "text": "Text"
+            })).unwrap());
+        }
+
+        manifest.slide_mut(3).unwrap().deleted_at = Some(5);
+
+        assert_eq!(manifest.published_notes().into_keys().collect::<Vec<_>>(), vec![1]);
+        assert_eq!(manifest.slide_texts().into_keys().collect::<Vec<_>>(), vec![1, 2]);
+    }
+}
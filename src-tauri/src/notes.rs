@@ -0,0 +1,93 @@
+use serde::Serialize;
+
+use crate::manifest;
+use crate::protocol;
+use crate::search;
+use crate::utility;
+
+
+// Longest note we keep, anything after that is cut off
+const MAX_NOTES_LENGTH: usize = 20_000;
+
+
+// A slide's notes as the presenter view sees them
+#[derive(Serialize, Clone)]
+pub struct SlideNotes {
+    pub slide: u16,
+    pub notes: String,
+    pub published: bool
+}
+
+
+pub async fn get(current_state: &utility::CurrentSlideState, slide: u16) -> Result<SlideNotes, String> {
+
+    current_state.manifest.lock().await
+        .slide(slide)
+        .map(|entry| SlideNotes { slide, notes: entry.notes.clone(), published: entry.notes_published })
+        .ok_or_else(|| "No such slide".into())
+}
+
+
+// Replaces the slide's notes. The audience only hears about it if they're published
+pub async fn set(current_state: &utility::CurrentSlideState, slide: u16, notes: &str) -> Result<(), String> {
+
+    let notes: String = notes.chars().take(MAX_NOTES_LENGTH).collect();
+
+    update(current_state, slide, |entry| entry.notes = notes).await
+}
+
+
+// Shares the slide's notes with the audience as handout notes, or takes them back
+pub async fn set_published(current_state: &utility::CurrentSlideState, slide: u16, published: bool) -> Result<(), String> {
+
+    update(current_state, slide, |entry| entry.notes_published = published).await
+}
+
+
+async fn update(
+    current_state: &utility::CurrentSlideState,
+    slide: u16,
+    change: impl FnOnce(&mut manifest::SlideEntry)
+) -> Result<(), String> {
+
+    let (was_public, now_public) = {
+
+        let mut manifest = current_state.manifest.lock().await;
+
+        let entry = manifest.slide_mut(slide)
+            .filter(|entry| entry.deleted_at.is_none())
+            .ok_or("No such slide")?;
+
+        let was_public = public_notes(entry);
+
+        change(entry);
+
+        let now_public = public_notes(entry);
+
+        if let Err(e) = manifest.save().await {
+            eprintln!("Failed to save the manifest: {}", e);
+        }
+
+        if let Some(entry) = manifest.slide(slide) {
+            search::update_slide(current_state, &manifest.session_id, entry).await;
+        }
+
+        (was_public, now_public)
+    };
+
+    // Private notes never leave the presenter's machine
+    if was_public != now_public {
+
+        let message = protocol::ServerMessage::NotesPublished(protocol::NotesPublished { slide, notes: now_public });
+
+        let _ = current_state.broadcast(message).await;
+    }
+
+    Ok(())
+}
+
+
+fn public_notes(entry: &manifest::SlideEntry) -> Option<String> {
+
+    (entry.notes_published && !entry.notes.is_empty()).then(|| entry.notes.clone())
+}
@@ -0,0 +1,452 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter};
+
+use crate::manifest;
+use crate::protocol;
+use crate::search;
+use crate::server;
+use crate::utility;
+
+
+// The OCR engine is the Tesseract command line tool, SLIDESHOW_TESSERACT can point at it when it's not on the PATH
+const TESSERACT: &str = "tesseract";
+
+// A slide that takes longer than this is given up on
+const OCR_TIMEOUT: Duration = Duration::from_secs(60);
+
+
+// Where a slide's text stands
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TextStatus {
+    #[default]
+    Pending, // waiting in the queue
+    Done,
+    Failed, // the engine ran but couldn't read the slide
+    Unavailable // no OCR engine installed, retried the next time the server starts
+}
+
+// What the slide viewer shows under the slide
+#[derive(Serialize, Clone)]
+pub struct SlideText {
+    pub slide: u16,
+    pub status: TextStatus,
+    pub text: Option<String>
+}
+
+
+// A slide waiting for OCR. It's known by its session too, because a slide can still be waiting when its session is
+// archived: it then waits with a copy of its image and its text goes into the kept manifest (see keep_waiting)
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct QueuedSlide {
+    pub session_id: String,
+    pub slide: u16
+}
+
+
+// Outside the assets folder, so the queue and the images it still needs survive a fresh session clearing it
+fn ocr_dir() -> PathBuf {
+
+    PathBuf::from("ocr")
+}
+
+
+// The slides still waiting for OCR, so neither restarting the server nor relaunching the app loses them
+fn queue_path() -> PathBuf {
+
+    ocr_dir().join("queue.json")
+}
+
+
+// The copy of a slide's image that waits here after its session was archived
+fn archived_image(session_id: &str, slide: u16) -> PathBuf {
+
+    ocr_dir().join(format!("{}-slide{}.png", session_id, slide))
+}
+
+
+async fn load_queue() -> VecDeque<QueuedSlide> {
+
+    tokio::fs::read_to_string(queue_path())
+        .await
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+
+async fn save_queue(queue: &VecDeque<QueuedSlide>) {
+
+    let result = match serde_json::to_string(queue) {
+        Ok(json) => match tokio::fs::create_dir_all(ocr_dir()).await {
+            Ok(_) => tokio::fs::write(queue_path(), json).await,
+            Err(e) => Err(e)
+        },
+        Err(e) => Err(e.into())
+    };
+
+    if let Err(e) = result {
+        eprintln!("Failed to save the OCR queue: {}", e);
+    }
+}
+
+
+fn is_waiting(entry: &manifest::SlideEntry) -> bool {
+
+    matches!(entry.text_status, TextStatus::Pending | TextStatus::Unavailable) && entry.deleted_at.is_none()
+}
+
+
+// Queues a fresh capture. The worker picks it up in the background, so capturing never waits for OCR
+pub async fn enqueue(current_state: &utility::CurrentSlideState, slide: u16) {
+
+    let session_id = current_state.manifest.lock().await.session_id.clone();
+
+    {
+        let mut queue = current_state.ocr_queue.lock().await;
+
+        let queued = QueuedSlide { session_id, slide };
+
+        if queue.contains(&queued) {
+            return;
+        }
+
+        queue.push_back(queued);
+
+        save_queue(&queue).await;
+    }
+
+    current_state.ocr_notify.notify_one();
+}
+
+
+// Called when a fresh session is about to clear the assets folder, with the session that was just archived.
+// Its slides that are still waiting keep a copy of their image and stay queued, so their text still comes in
+pub async fn keep_waiting(session: &manifest::SessionManifest) {
+
+    let mut queue = load_queue().await;
+
+    // Whatever was queued for it pointed at the assets folder
+    queue.retain(|queued| queued.session_id != session.session_id);
+
+    if let Err(e) = tokio::fs::create_dir_all(ocr_dir()).await {
+        eprintln!("Slides of {} that are waiting for OCR won't get their text: {}", session.session_id, e);
+        return;
+    }
+
+    for entry in session.slides.iter().filter(|entry| is_waiting(entry)) {
+
+        let copy = tokio::fs::copy(PathBuf::from("assets").join(&entry.file), archived_image(&session.session_id, entry.id)).await;
+
+        match copy {
+            Ok(_) => queue.push_back(QueuedSlide { session_id: session.session_id.clone(), slide: entry.id }),
+            Err(e) => eprintln!("Slide {} of {} won't get its text: {}", entry.id, session.session_id, e)
+        }
+    }
+
+    save_queue(&queue).await;
+}
+
+
+pub async fn slide_text(current_state: &utility::CurrentSlideState, slide: u16) -> Result<SlideText, String> {
+
+    current_state.manifest.lock().await
+        .slide(slide)
+        .map(|entry| SlideText { slide, status: entry.text_status, text: entry.text.clone() })
+        .ok_or_else(|| "No such slide".into())
+}
+
+
+// The next slide to read. Without an engine, slides of past sessions keep waiting for one, while the current
+// session's are still taken so they can be marked unavailable
+fn next_in_line(queue: &VecDeque<QueuedSlide>, current_session: &str, engine_found: bool) -> Option<QueuedSlide> {
+
+    queue.iter()
+        .find(|queued| engine_found || queued.session_id == current_session)
+        .cloned()
+}
+
+
+// Runs for as long as the server does. The queue is read back from disk first, along with any slide of
+// the session that is still pending or was waiting for an engine to be installed
+pub fn start_worker(app_handle: AppHandle, current_state: utility::CurrentSlideState) {
+
+    let mut shutdown_rx = current_state.shutdown_tx.subscribe();
+
+    tokio::spawn(async move {
+
+        let mut engine_found = engine_installed().await;
+
+        if !engine_found {
+            eprintln!(
+                "No OCR engine found ({}). Install Tesseract, or set SLIDESHOW_TESSERACT to where it is, to read the text on slides",
+                engine()
+            );
+        }
+
+        {
+            let mut queue = load_queue().await;
+
+            let manifest = current_state.manifest.lock().await;
+
+            for entry in manifest.slides.iter().filter(|entry| is_waiting(entry)) {
+
+                let queued = QueuedSlide { session_id: manifest.session_id.clone(), slide: entry.id };
+
+                if !queue.contains(&queued) {
+                    queue.push_back(queued);
+                }
+            }
+
+            save_queue(&queue).await;
+
+            *current_state.ocr_queue.lock().await = queue;
+        }
+
+        loop {
+
+            let current_session = current_state.manifest.lock().await.session_id.clone();
+
+            let next = next_in_line(&*current_state.ocr_queue.lock().await, &current_session, engine_found);
+
+            let Some(queued) = next else {
+
+                tokio::select! {
+                    _ = current_state.ocr_notify.notified() => continue,
+                    _ = server::wait_for_shutdown(&mut shutdown_rx) => break
+                }
+            };
+
+            let slide = queued.slide;
+
+            if queued.session_id == current_session {
+
+                // Deleted while it was waiting, nobody will look for its text
+                let deleted = current_state.manifest.lock().await
+                    .slide(slide)
+                    .is_none_or(|entry| entry.deleted_at.is_some());
+
+                if !deleted {
+
+                    let image = PathBuf::from("assets").join(format!("slide{}.png", slide));
+
+                    // Stopping the server in the middle drops the engine (kill_on_drop) and leaves the slide queued
+                    let result = tokio::select! {
+                        result = read_slide(engine_found, &image, slide) => result,
+                        _ = server::wait_for_shutdown(&mut shutdown_rx) => break
+                    };
+
+                    engine_found &= result != Err(TextStatus::Unavailable);
+
+                    finish(&current_state, slide, result).await;
+                }
+
+            } else {
+
+                let image = archived_image(&queued.session_id, slide);
+
+                // The copy is gone, there's nothing left to read
+                if tokio::fs::try_exists(&image).await.unwrap_or(false) {
+
+                    let result = tokio::select! {
+                        result = read_slide(engine_found, &image, slide) => result,
+                        _ = server::wait_for_shutdown(&mut shutdown_rx) => break
+                    };
+
+                    // The engine went away, the slide stays queued for the next start
+                    if result == Err(TextStatus::Unavailable) {
+                        engine_found = false;
+                        continue;
+                    }
+
+                    finish_archived(&current_state, &queued, result).await;
+                }
+            }
+
+            {
+                let mut queue = current_state.ocr_queue.lock().await;
+
+                queue.retain(|other| *other != queued);
+
+                save_queue(&queue).await;
+            }
+
+            if queued.session_id == current_session {
+                app_handle.emit("slide_text_ready", slide);
+            }
+        }
+    });
+}
+
+
+fn apply_result(entry: &mut manifest::SlideEntry, result: Result<String, TextStatus>) {
+
+    match result {
+
+        Ok(text) => {
+            println!("Read {} characters from slide {}.", text.len(), entry.id);
+
+            entry.text = Some(text);
+            entry.text_status = TextStatus::Done;
+        }
+
+        Err(status) => entry.text_status = status
+    }
+}
+
+
+async fn finish(current_state: &utility::CurrentSlideState, slide: u16, result: Result<String, TextStatus>) {
+
+    let text = {
+        let mut manifest = current_state.manifest.lock().await;
+
+        let Some(entry) = manifest.slide_mut(slide) else {
+            return;
+        };
+
+        apply_result(entry, result);
+
+        let text = entry.text.clone();
+
+        if let Err(e) = manifest.save().await {
+            eprintln!("Failed to save the manifest: {}", e);
+        }
+
+        if let Some(entry) = manifest.slide(slide) {
+            search::update_slide(current_state, &manifest.session_id, entry).await;
+        }
+
+        text
+    };
+
+    // The text-only view fills the slide in as soon as it's read
+    if let Some(text) = text {
+
+        let message = protocol::ServerMessage::SlideText(protocol::SlideText { slide, text });
+
+        let _ = current_state.broadcast(message).await;
+    }
+}
+
+
+// A slide of a past session: the text goes into its kept manifest, and the copy of its image isn't needed anymore
+async fn finish_archived(current_state: &utility::CurrentSlideState, queued: &QueuedSlide, result: Result<String, TextStatus>) {
+
+    search::update_archived_slide(current_state, &queued.session_id, queued.slide, |entry| apply_result(entry, result)).await;
+
+    if let Err(e) = tokio::fs::remove_file(archived_image(&queued.session_id, queued.slide)).await {
+        eprintln!("Failed to remove the OCR copy of slide {} of {}: {}", queued.slide, queued.session_id, e);
+    }
+}
+
+
+fn engine() -> String {
+
+    std::env::var("SLIDESHOW_TESSERACT").unwrap_or_else(|_| TESSERACT.to_string())
+}
+
+
+// Whether the engine runs at all, checked when the worker starts
+async fn engine_installed() -> bool {
+
+    let version = tokio::process::Command::new(engine())
+        .arg("--version")
+        .kill_on_drop(true)
+        .output();
+
+    matches!(tokio::time::timeout(OCR_TIMEOUT, version).await, Ok(Ok(output)) if output.status.success())
+}
+
+
+// Skips the engine when we already know there is none
+async fn read_slide(engine_found: bool, image: &Path, slide: u16) -> Result<String, TextStatus> {
+
+    if !engine_found {
+        return Err(TextStatus::Unavailable);
+    }
+
+    recognize(image, slide).await
+}
+
+
+// Runs the engine on the slide as captured (without the ink) and returns what it read
+async fn recognize(image: &Path, slide: u16) -> Result<String, TextStatus> {
+
+    let engine = engine();
+
+    let output = tokio::process::Command::new(&engine)
+        .arg(image)
+        .arg("stdout")
+        .kill_on_drop(true)
+        .output();
+
+    match tokio::time::timeout(OCR_TIMEOUT, output).await {
+
+        Ok(Ok(output)) if output.status.success() => {
+
+            // Without the trailing spaces and the form feed Tesseract ends the page with
+            let text = String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(str::trim_end)
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            Ok(text.trim().to_string())
+        }
+
+        Ok(Ok(output)) => {
+            eprintln!("OCR failed on slide {}: {}", slide, String::from_utf8_lossy(&output.stderr).trim());
+            Err(TextStatus::Failed)
+        }
+
+        Ok(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            eprintln!("No OCR engine found ({}), slide {} stays without text for now", engine, slide);
+            Err(TextStatus::Unavailable)
+        }
+
+        Ok(Err(e)) => {
+            eprintln!("Could not run the OCR engine on slide {}: {}", slide, e);
+            Err(TextStatus::Failed)
+        }
+
+        Err(_) => {
+            eprintln!("OCR on slide {} took too long, giving up", slide);
+            Err(TextStatus::Failed)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queued(session_id: &str, slide: u16) -> QueuedSlide {
+        QueuedSlide { session_id: session_id.to_string(), slide }
+    }
+
+    #[test]
+    fn past_sessions_wait_for_an_engine() {
+        let queue = VecDeque::from([queued("old", 3), queued("current", 1), queued("current", 2)]);
+
+        assert_eq!(next_in_line(&queue, "current", true), Some(queued("old", 3)));
+        assert_eq!(next_in_line(&queue, "current", false), Some(queued("current", 1)));
+
+        let only_old = VecDeque::from([queued("old", 3)]);
+        assert_eq!(next_in_line(&only_old, "current", false), None);
+    }
+
+    #[test]
+    fn the_queue_file_keeps_which_session_a_slide_belongs_to() {
+        let queue = VecDeque::from([queued("session-1", 4)]);
+
+        let json = serde_json::to_string(&queue).unwrap();
+
+        assert_eq!(json, r#"[{"session_id":"session-1","slide":4}]"#);
+        assert_eq!(serde_json::from_str::<VecDeque<QueuedSlide>>(&json).unwrap(), queue);
+    }
+}
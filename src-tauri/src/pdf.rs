@@ -0,0 +1,409 @@
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use pdf_writer::{Content, Filter, Finish, Name, Pdf, Rect, Ref, Str};
+use xcap::image;
+
+
+// A4, in points
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 50.0;
+const CONTENT_WIDTH: f32 = PAGE_WIDTH - 2.0 * MARGIN;
+
+// Slides are scaled down to the page width, and tall ones to this height
+const MAX_IMAGE_HEIGHT: f32 = 400.0;
+const GAP: f32 = 12.0;
+
+const TITLE_SIZE: f32 = 20.0;
+const HEADING_SIZE: f32 = 14.0;
+const TEXT_SIZE: f32 = 11.0;
+const LINE_HEIGHT: f32 = 1.4; // times the font size
+
+// The standard Helvetica every PDF reader has, so no font needs to be embedded
+const REGULAR: Name = Name(b"F1");
+const BOLD: Name = Name(b"F2");
+
+// Helvetica's glyph widths for ' ' to '~', in thousandths of the font size
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556,
+    1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556,
+    333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556,
+    556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584
+];
+
+
+// One slide in the export: a heading, the slide itself if it could be read, and the attendee's note
+pub struct ExportedPage {
+    pub heading: String,
+    pub png: Option<Vec<u8>>,
+    pub note: String
+}
+
+// The document as it's being written, a page at a time
+struct Document {
+    pdf: Pdf,
+    next_ref: i32,
+    page_tree: Ref,
+    regular: Ref,
+    bold: Ref,
+    pages: Vec<Ref>,
+    content: Content, // the page being written
+    images: Vec<(String, Ref)>, // the images on it, by the name the content refers to them with
+    y: f32, // where the next thing goes, from the bottom of the page
+    page_open: bool
+}
+
+
+// The attendee's notes as a PDF: the title, then each slide with its note below it
+pub fn notes_document(title: &str, subtitle: &str, slides: &[ExportedPage]) -> Vec<u8> {
+
+    let mut document = Document::new();
+
+    document.text(title, BOLD, TITLE_SIZE);
+    document.text(subtitle, REGULAR, TEXT_SIZE);
+
+    if slides.is_empty() {
+        document.space();
+        document.text("No bookmarks or notes yet.", REGULAR, TEXT_SIZE);
+    }
+
+    for slide in slides {
+
+        document.space();
+        document.text(&slide.heading, BOLD, HEADING_SIZE);
+
+        if let Some(png) = &slide.png {
+            document.image(png);
+        }
+
+        document.text(&slide.note, REGULAR, TEXT_SIZE);
+    }
+
+    document.finish()
+}
+
+
+impl Document {
+
+    fn new() -> Self {
+
+        let mut pdf = Pdf::new();
+
+        let catalog = Ref::new(1);
+        let page_tree = Ref::new(2);
+        let regular = Ref::new(3);
+        let bold = Ref::new(4);
+
+        pdf.catalog(catalog).pages(page_tree);
+        pdf.type1_font(regular).base_font(Name(b"Helvetica")).encoding_predefined(Name(b"WinAnsiEncoding"));
+        pdf.type1_font(bold).base_font(Name(b"Helvetica-Bold")).encoding_predefined(Name(b"WinAnsiEncoding"));
+
+        Document {
+            pdf,
+            next_ref: 5,
+            page_tree,
+            regular,
+            bold,
+            pages: Vec::new(),
+            content: Content::new(),
+            images: Vec::new(),
+            y: 0.0,
+            page_open: false
+        }
+    }
+
+    fn next_ref(&mut self) -> Ref {
+
+        let id = Ref::new(self.next_ref);
+        self.next_ref += 1;
+
+        id
+    }
+
+    // Moves on to a new page when there's less than `height` left on this one
+    fn make_room(&mut self, height: f32) {
+
+        if self.page_open && self.y - height >= MARGIN {
+            return;
+        }
+
+        self.finish_page();
+
+        self.page_open = true;
+        self.y = PAGE_HEIGHT - MARGIN;
+    }
+
+    fn finish_page(&mut self) {
+
+        if !self.page_open {
+            return;
+        }
+
+        self.page_open = false;
+
+        let page_id = self.next_ref();
+        let content_id = self.next_ref();
+
+        let content = std::mem::replace(&mut self.content, Content::new()).finish();
+        self.pdf.stream(content_id, &content);
+
+        let mut page = self.pdf.page(page_id);
+
+        page.media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT));
+        page.parent(self.page_tree);
+        page.contents(content_id);
+
+        let mut resources = page.resources();
+        resources.fonts().pair(REGULAR, self.regular).pair(BOLD, self.bold);
+
+        let mut x_objects = resources.x_objects();
+
+        for (name, id) in self.images.drain(..) {
+            x_objects.pair(Name(name.as_bytes()), id);
+        }
+
+        x_objects.finish();
+        resources.finish();
+        page.finish();
+
+        self.pages.push(page_id);
+    }
+
+    // Wrapped to the page width, continuing on the next page if it has to
+    fn text(&mut self, text: &str, font: Name, size: f32) {
+
+        for line in wrap(text, size, CONTENT_WIDTH) {
+
+            self.make_room(size * LINE_HEIGHT);
+
+            self.y -= size * LINE_HEIGHT;
+
+            // The baseline sits a bit above the bottom of the line, leaving room for descenders
+            self.content
+                .begin_text()
+                .set_font(font, size)
+                .next_line(MARGIN, self.y + size * (LINE_HEIGHT - 1.0))
+                .show(Str(&win_ansi(&line)))
+                .end_text();
+        }
+    }
+
+    fn space(&mut self) {
+
+        self.y -= GAP;
+    }
+
+    // The slide scaled to fit, kept as its raw pixels (compressed) since PDF can't take a PNG as is
+    fn image(&mut self, png: &[u8]) {
+
+        let pixels = match image::load_from_memory(png) {
+            Ok(decoded) => decoded.to_rgb8(),
+            Err(e) => {
+                eprintln!("Failed to read a slide for the PDF export: {}", e);
+                return;
+            }
+        };
+
+        let (pixel_width, pixel_height) = pixels.dimensions();
+
+        let scale = (CONTENT_WIDTH / pixel_width as f32).min(MAX_IMAGE_HEIGHT / pixel_height as f32);
+        let width = pixel_width as f32 * scale;
+        let height = pixel_height as f32 * scale;
+
+        self.make_room(height + GAP);
+
+        let id = self.next_ref();
+        let samples = compress_to_vec_zlib(pixels.as_raw(), 6);
+
+        let mut xobject = self.pdf.image_xobject(id, &samples);
+        xobject.filter(Filter::FlateDecode);
+        xobject.width(pixel_width as i32);
+        xobject.height(pixel_height as i32);
+        xobject.color_space().device_rgb();
+        xobject.bits_per_component(8);
+        xobject.finish();
+
+        let name = format!("Im{}", id.get());
+
+        self.y -= height + GAP / 2.0;
+
+        self.content
+            .save_state()
+            .transform([width, 0.0, 0.0, height, MARGIN, self.y])
+            .x_object(Name(name.as_bytes()))
+            .restore_state();
+
+        self.images.push((name, id));
+
+        self.y -= GAP / 2.0;
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+
+        // Even an empty document has a page
+        self.make_room(0.0);
+        self.finish_page();
+
+        let count = self.pages.len() as i32;
+        self.pdf.pages(self.page_tree).kids(self.pages.iter().copied()).count(count);
+
+        self.pdf.finish()
+    }
+}
+
+
+// The text in the WinAnsi encoding the standard fonts use. What it doesn't have becomes '?'
+fn win_ansi(text: &str) -> Vec<u8> {
+
+    text.chars()
+        .map(|c| if c == '\t' { ' ' } else { c })
+        .filter(|c| !c.is_control())
+        .map(|c| match c {
+            ' '..='~' | '\u{a0}'..='\u{ff}' => c as u32 as u8,
+            '€' => 0x80,
+            '‚' => 0x82,
+            '„' => 0x84,
+            '…' => 0x85,
+            '‘' => 0x91,
+            '’' => 0x92,
+            '“' => 0x93,
+            '”' => 0x94,
+            '•' => 0x95,
+            '–' => 0x96,
+            '—' => 0x97,
+            '™' => 0x99,
+            _ => b'?'
+        })
+        .collect()
+}
+
+
+fn text_width(text: &str, size: f32) -> f32 {
+
+    let units: u32 = win_ansi(text).iter()
+        .map(|&byte| match byte {
+            b' '..=b'~' => HELVETICA_WIDTHS[(byte - b' ') as usize] as u32,
+            // Outside ASCII, wide enough for any of them so lines never run off the page
+            _ => 1000
+        })
+        .sum();
+
+    units as f32 * size / 1000.0
+}
+
+
+// Lines that fit in `width`, broken between words. The note's own line breaks are kept
+fn wrap(text: &str, size: f32, width: f32) -> Vec<String> {
+
+    let mut lines = Vec::new();
+
+    for paragraph in text.lines() {
+
+        let mut line = String::new();
+
+        for word in paragraph.split_whitespace() {
+
+            let joined = if line.is_empty() { word.to_string() } else { format!("{} {}", line, word) };
+
+            if text_width(&joined, size) <= width {
+                line = joined;
+                continue;
+            }
+
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+
+            // A word that's wider than the page on its own is broken wherever it has to be
+            for c in word.chars() {
+
+                line.push(c);
+
+                if text_width(&line, size) > width && line.chars().count() > 1 {
+                    line.pop();
+                    lines.push(std::mem::replace(&mut line, c.to_string()));
+                }
+            }
+        }
+
+        lines.push(line);
+    }
+
+    lines
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::{ImageFormat, Rgb, RgbImage};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        RgbImage::from_pixel(width, height, Rgb([200, 30, 30])).write_to(&mut bytes, ImageFormat::Png).unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn lines_fit_the_page() {
+        let note = "A fairly long note that certainly does not fit on one line of the page, so it has to wrap somewhere.\n\nSecond paragraph";
+        let lines = wrap(note, TEXT_SIZE, 200.0);
+
+        assert!(lines.len() > 3);
+        assert!(lines.iter().all(|line| text_width(line, TEXT_SIZE) <= 200.0));
+        assert!(lines.contains(&String::new()), "the blank line between paragraphs is kept");
+        assert_eq!(lines.last().unwrap(), "Second paragraph");
+
+        // Nothing is lost, only the spaces the lines were broken at
+        assert_eq!(lines.concat().replace(' ', ""), note.replace([' ', '\n'], ""));
+    }
+
+    #[test]
+    fn words_wider_than_the_page_are_broken() {
+        let lines = wrap(&"W".repeat(100), TEXT_SIZE, 100.0);
+
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|line| text_width(line, TEXT_SIZE) <= 100.0));
+        assert_eq!(lines.concat(), "W".repeat(100));
+    }
+
+    #[test]
+    fn text_is_encoded_for_the_standard_fonts() {
+        assert_eq!(win_ansi("Grüße – “ok”\t★"), b"Gr\xfc\xdfe \x96 \x93ok\x94 ?".to_vec());
+    }
+
+    #[test]
+    fn the_export_is_a_pdf_with_the_slides_in_it() {
+        let slides = [
+            ExportedPage { heading: "Slide 1 (bookmarked)".to_string(), png: Some(png(160, 90)), note: "Remember this".to_string() },
+            ExportedPage { heading: "Slide 2".to_string(), png: Some(png(160, 90)), note: String::new() },
+            ExportedPage { heading: "Slide 3".to_string(), png: None, note: "Only a note".to_string() }
+        ];
+
+        let document = notes_document("My notes", "From session-1", &slides);
+        let text = String::from_utf8_lossy(&document);
+
+        assert!(document.starts_with(b"%PDF-"));
+        assert!(text.trim_end().ends_with("%%EOF"));
+        assert_eq!(text.matches("/Subtype /Image").count(), 2);
+        assert!(text.contains("(Remember this) Tj"));
+        assert!(text.contains("(Only a note) Tj"));
+    }
+
+    #[test]
+    fn long_notes_continue_on_the_next_page() {
+        let slides = [ExportedPage { heading: "Slide 1".to_string(), png: Some(png(100, 100)), note: "line\n".repeat(200) }];
+
+        let document = notes_document("My notes", "From session-1", &slides);
+        let text = String::from_utf8_lossy(&document);
+
+        // 200 lines take about four pages, and every line is still there
+        let pages: usize = text.split("/Count ").nth(1).unwrap().split(|c: char| !c.is_ascii_digit()).next().unwrap().parse().unwrap();
+
+        assert!(pages >= 4, "{} pages", pages);
+        assert_eq!(text.matches("/Type /Page\n").count(), pages);
+        assert_eq!(text.matches("(line) Tj").count(), 200);
+    }
+}
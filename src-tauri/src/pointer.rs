@@ -0,0 +1,131 @@
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use rdev::{EventType, Key};
+
+use crate::protocol;
+use crate::utility;
+
+
+// At most one pointer update per this long, phones on a busy Wi-Fi don't need more than ~20 a second
+const POINTER_INTERVAL: Duration = Duration::from_millis(50);
+
+
+// The screen the captures come from, in the global coordinates rdev reports the mouse in
+#[derive(Clone, Copy)]
+struct ScreenBounds {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64
+}
+
+// Where the laser pointer relay is at. Only touched from the rdev worker (and the toggle command)
+#[derive(Default)]
+pub struct PointerTracker {
+    modifier_held: bool, // the pointer is only relayed while Ctrl is held
+    visible: bool, // whether the audience is currently being shown the pointer
+    bounds: Option<ScreenBounds>, // looked up again every time Ctrl goes down, in case the screen changed
+    last_sent: Option<Instant>
+}
+
+
+// Called for every rdev event while the server is running
+pub async fn handle_event(current_state: &utility::CurrentSlideState, event_type: EventType) {
+
+    match event_type {
+
+        EventType::KeyPress(Key::ControlLeft | Key::ControlRight) => {
+
+            if !current_state.pointer_relay.load(Ordering::SeqCst) {
+                return;
+            }
+
+            let mut tracker = current_state.pointer.lock().await;
+
+            // Key repeat sends this over and over while the key is held
+            if !tracker.modifier_held {
+                tracker.modifier_held = true;
+                tracker.bounds = screen_bounds();
+            }
+        }
+
+        EventType::KeyRelease(Key::ControlLeft | Key::ControlRight) => {
+
+            let mut tracker = current_state.pointer.lock().await;
+
+            tracker.modifier_held = false;
+
+            hide(current_state, &mut tracker);
+        }
+
+        EventType::MouseMove { x, y } => {
+
+            let mut tracker = current_state.pointer.lock().await;
+
+            if !tracker.modifier_held || !current_state.pointer_relay.load(Ordering::SeqCst) {
+                return;
+            }
+
+            let Some(bounds) = tracker.bounds else {
+                return;
+            };
+
+            if tracker.last_sent.is_some_and(|last| last.elapsed() < POINTER_INTERVAL) {
+                return;
+            }
+
+            // 0..1 across the captured screen, so it lands in the same spot on any size of slide
+            let x = (x - bounds.x) / bounds.width;
+            let y = (y - bounds.y) / bounds.height;
+
+            if !(0.0..=1.0).contains(&x) || !(0.0..=1.0).contains(&y) {
+                // Off the captured screen, e.g. on the presenter's second monitor
+                hide(current_state, &mut tracker);
+                return;
+            }
+
+            tracker.visible = true;
+            tracker.last_sent = Some(Instant::now());
+
+            let _ = current_state.broadcast_volatile(protocol::ServerMessage::Pointer(protocol::Pointer { visible: true, x, y }));
+        }
+
+        _ => {}
+    }
+}
+
+
+// Takes the pointer off the audience's screens, if it's on them
+pub fn hide(current_state: &utility::CurrentSlideState, tracker: &mut PointerTracker) {
+
+    if !tracker.visible {
+        return;
+    }
+
+    tracker.visible = false;
+    tracker.last_sent = None;
+
+    let _ = current_state.broadcast_volatile(protocol::ServerMessage::Pointer(protocol::Pointer { visible: false, x: 0.0, y: 0.0 }));
+}
+
+
+// The first monitor, the same one take_screenshot_and_save captures
+fn screen_bounds() -> Option<ScreenBounds> {
+
+    let monitor = xcap::Monitor::all().ok()?.into_iter().next()?;
+
+    let width = monitor.width().ok()? as f64;
+    let height = monitor.height().ok()? as f64;
+
+    if width == 0.0 || height == 0.0 {
+        return None;
+    }
+
+    Some(ScreenBounds {
+        x: monitor.x().ok()? as f64,
+        y: monitor.y().ok()? as f64,
+        width,
+        height
+    })
+}
@@ -0,0 +1,455 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::manifest;
+use crate::protocol;
+use crate::utility;
+
+
+const MAX_QUESTION_LENGTH: usize = 200;
+const MAX_OPTION_LENGTH: usize = 100;
+const MAX_OPTIONS: usize = 10;
+
+
+// A poll prepared before the talk. Drafts live in the app data folder, so they survive new sessions
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PollDraft {
+    pub id: u64,
+    pub question: String,
+    pub options: Vec<String>
+}
+
+// A poll that was put to the audience during this session
+#[derive(Serialize, Clone)]
+pub struct Poll {
+    pub id: u64,
+    pub question: String,
+    pub options: Vec<String>,
+    pub counts: Vec<usize>, // votes per option, in the same order
+    #[serde(skip)]
+    pub votes: HashMap<String, usize>, // which option each attendee picked, by attendees::voter_id. counts is what gets saved and shown
+    pub opened_at: u64,
+    pub closed_at: Option<u64>,
+    pub published: bool // whether the audience was shown the results
+}
+
+// Every poll of the session, saved as assets/polls.json
+#[derive(Serialize, Default)]
+pub struct PollBoard {
+    next_id: u64,
+    polls: Vec<Poll>,
+    #[serde(skip)]
+    unsaved_votes: bool // votes are saved in batches, see save_votes
+}
+
+
+impl Poll {
+
+    fn info(&self) -> protocol::PollInfo {
+
+        protocol::PollInfo {
+            poll_id: self.id,
+            question: self.question.clone(),
+            options: self.options.clone()
+        }
+    }
+
+    fn results(&self) -> protocol::PollResults {
+
+        protocol::PollResults {
+            poll_id: self.id,
+            question: self.question.clone(),
+            options: self.options.clone(),
+            counts: self.counts.clone()
+        }
+    }
+}
+
+impl PollBoard {
+
+    // The poll the audience can vote in right now, for slidesInfo
+    pub fn open_poll(&self) -> Option<protocol::PollInfo> {
+
+        self.polls.iter().find(|poll| poll.closed_at.is_none()).map(Poll::info)
+    }
+
+    // The results the presenter shared so far, for slidesInfo
+    pub fn published_results(&self) -> Vec<protocol::PollResults> {
+
+        self.polls.iter().filter(|poll| poll.published).map(Poll::results).collect()
+    }
+
+    // Counts the attendee's vote, voting again changes it. The results when the audience should see them
+    fn vote(&mut self, poll_id: u64, voter: String, option: usize) -> Result<Option<protocol::PollResults>, ()> {
+
+        let poll = self.polls.iter_mut()
+            .find(|poll| poll.id == poll_id && poll.closed_at.is_none())
+            .ok_or(())?;
+
+        if option >= poll.options.len() {
+            return Err(());
+        }
+
+        if let Some(previous) = poll.votes.insert(voter, option) {
+            poll.counts[previous] -= 1;
+        }
+
+        poll.counts[option] += 1;
+
+        let published_results = poll.published.then(|| poll.results());
+
+        self.unsaved_votes = true;
+
+        Ok(published_results)
+    }
+
+    async fn save(&mut self) {
+
+        self.unsaved_votes = false;
+
+        let result = match serde_json::to_string_pretty(self) {
+            Ok(json) => tokio::fs::write(polls_path(), json).await,
+            Err(e) => Err(e.into())
+        };
+
+        if let Err(e) = result {
+            eprintln!("Failed to save the polls: {}", e);
+        }
+    }
+}
+
+
+pub fn polls_path() -> PathBuf {
+
+    PathBuf::from("assets").join("polls.json")
+}
+
+
+fn drafts_path(dir: &Path) -> PathBuf {
+
+    dir.join("polls.json")
+}
+
+
+// The prepared polls, none if nothing was prepared yet
+pub fn load_drafts(dir: &Path) -> Vec<PollDraft> {
+
+    std::fs::read_to_string(drafts_path(dir))
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+
+// Adds a draft (id 0) or replaces the one with the same id
+pub fn save_draft(dir: &Path, question: &str, options: &[String], id: u64) -> Result<PollDraft, String> {
+
+    let (question, options) = clean(question, options)?;
+
+    let mut drafts = load_drafts(dir);
+
+    let id = if id == 0 { drafts.iter().map(|draft| draft.id).max().unwrap_or(0) + 1 } else { id };
+
+    let draft = PollDraft { id, question, options };
+
+    match drafts.iter_mut().find(|existing| existing.id == id) {
+        Some(existing) => *existing = draft.clone(),
+        None => drafts.push(draft.clone())
+    }
+
+    write_drafts(dir, &drafts)?;
+
+    Ok(draft)
+}
+
+
+pub fn delete_draft(dir: &Path, id: u64) -> Result<(), String> {
+
+    let mut drafts = load_drafts(dir);
+
+    drafts.retain(|draft| draft.id != id);
+
+    write_drafts(dir, &drafts)
+}
+
+
+fn write_drafts(dir: &Path, drafts: &[PollDraft]) -> Result<(), String> {
+
+    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+
+    let json = serde_json::to_string_pretty(drafts).map_err(|e| e.to_string())?;
+
+    std::fs::write(drafts_path(dir), json).map_err(|e| e.to_string())
+}
+
+
+// Trims everything, drops empty options and checks there is something to vote on
+fn clean(question: &str, options: &[String]) -> Result<(String, Vec<String>), String> {
+
+    let question: String = question.trim().chars().take(MAX_QUESTION_LENGTH).collect();
+
+    let options: Vec<String> = options.iter()
+        .map(|option| option.trim().chars().take(MAX_OPTION_LENGTH).collect::<String>())
+        .filter(|option| !option.is_empty())
+        .take(MAX_OPTIONS)
+        .collect();
+
+    if question.is_empty() {
+        return Err("The poll needs a question".into());
+    }
+
+    if options.len() < 2 {
+        return Err("A poll needs at least two options".into());
+    }
+
+    Ok((question, options))
+}
+
+
+// Puts a poll to the audience. Only one poll is open at a time, so any open one is closed first
+pub async fn open(current_state: &utility::CurrentSlideState, question: &str, options: &[String]) -> Result<u64, String> {
+
+    let (question, options) = clean(question, options)?;
+
+    let (closed, poll) = {
+
+        let mut board = current_state.polls.lock().await;
+
+        let closed = close_open_poll(&mut board);
+
+        board.next_id += 1;
+
+        let poll = Poll {
+            id: board.next_id,
+            counts: vec![0; options.len()],
+            question,
+            options,
+            votes: HashMap::new(),
+            opened_at: manifest::now_millis(),
+            closed_at: None,
+            published: false
+        };
+
+        board.polls.push(poll.clone());
+        board.save().await;
+
+        (closed, poll)
+    };
+
+    if let Some(poll_id) = closed {
+        let _ = current_state.broadcast(protocol::ServerMessage::PollClosed(protocol::PollClosed { poll_id })).await;
+    }
+
+    let _ = current_state.broadcast(protocol::ServerMessage::PollOpened(poll.info())).await;
+
+    current_state.polls_notify.notify_one();
+
+    Ok(poll.id)
+}
+
+
+fn close_open_poll(board: &mut PollBoard) -> Option<u64> {
+
+    let poll = board.polls.iter_mut().find(|poll| poll.closed_at.is_none())?;
+
+    poll.closed_at = Some(manifest::now_millis());
+
+    Some(poll.id)
+}
+
+
+// Stops taking votes
+pub async fn close(current_state: &utility::CurrentSlideState, poll_id: u64) -> Result<(), String> {
+
+    {
+        let mut board = current_state.polls.lock().await;
+
+        let poll = board.polls.iter_mut()
+            .find(|poll| poll.id == poll_id)
+            .ok_or("No such poll")?;
+
+        if poll.closed_at.is_some() {
+            return Ok(());
+        }
+
+        poll.closed_at = Some(manifest::now_millis());
+
+        board.save().await;
+    }
+
+    let _ = current_state.broadcast(protocol::ServerMessage::PollClosed(protocol::PollClosed { poll_id })).await;
+
+    current_state.polls_notify.notify_one();
+
+    Ok(())
+}
+
+
+// Shows the audience the results. Publishing a poll that's still open shows them as they are now,
+// and later votes are published as they come in
+pub async fn publish(current_state: &utility::CurrentSlideState, poll_id: u64) -> Result<(), String> {
+
+    let results = {
+
+        let mut board = current_state.polls.lock().await;
+
+        let poll = board.polls.iter_mut()
+            .find(|poll| poll.id == poll_id)
+            .ok_or("No such poll")?;
+
+        poll.published = true;
+
+        let results = poll.results();
+
+        board.save().await;
+
+        results
+    };
+
+    let _ = current_state.broadcast(protocol::ServerMessage::PollResults(results)).await;
+
+    current_state.polls_notify.notify_one();
+
+    Ok(())
+}
+
+
+// One vote per attendee, voting again changes the vote.
+// The file isn't written here, a popular poll would rewrite it for every vote. See save_votes
+pub async fn vote(current_state: &utility::CurrentSlideState, client_id: u64, poll_id: u64, option: usize) {
+
+    let Some(voter) = current_state.clients.lock().await.get(&client_id).map(|client| client.voter.clone()) else {
+        return;
+    };
+
+    let Ok(published_results) = current_state.polls.lock().await.vote(poll_id, voter, option) else {
+        return;
+    };
+
+    if let Some(results) = published_results {
+        let _ = current_state.broadcast(protocol::ServerMessage::PollResults(results)).await;
+    }
+
+    current_state.polls_notify.notify_one();
+}
+
+
+// Writes the votes that came in since the last save. Called by the presenter's live results loop,
+// so a burst of votes is one write instead of one each
+pub async fn save_votes(current_state: &utility::CurrentSlideState) {
+
+    let mut board = current_state.polls.lock().await;
+
+    if board.unsaved_votes {
+        board.save().await;
+    }
+}
+
+
+// Every poll of the session, newest first, for the presenter's live results
+pub async fn list(current_state: &utility::CurrentSlideState) -> Vec<Poll> {
+
+    let mut polls = current_state.polls.lock().await.polls.clone();
+
+    polls.reverse();
+
+    polls
+}
+
+
+// A fresh session starts without polls (the drafts stay)
+pub async fn reset(current_state: &utility::CurrentSlideState) {
+
+    *current_state.polls.lock().await = PollBoard::default();
+
+    current_state.polls_notify.notify_one();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board_with_poll() -> PollBoard {
+        PollBoard {
+            next_id: 1,
+            polls: vec![Poll {
+                id: 1,
+                question: "Tabs or spaces?".to_string(),
+                options: vec!["Tabs".to_string(), "Spaces".to_string()],
+                counts: vec![0, 0],
+                votes: HashMap::new(),
+                opened_at: 0,
+                closed_at: None,
+                published: false
+            }],
+            unsaved_votes: false
+        }
+    }
+
+    #[test]
+    fn an_attendee_has_one_vote_however_often_they_reconnect() {
+        let mut board = board_with_poll();
+
+        assert!(board.vote(1, "attendee".to_string(), 0).is_ok());
+        assert!(board.vote(1, "attendee".to_string(), 0).is_ok());
+        assert_eq!(board.polls[0].counts, vec![1, 0]);
+
+        // Voting again moves the vote
+        assert!(board.vote(1, "attendee".to_string(), 1).is_ok());
+        assert!(board.vote(1, "someone else".to_string(), 1).is_ok());
+        assert_eq!(board.polls[0].counts, vec![0, 2]);
+
+        assert!(board.unsaved_votes);
+    }
+
+    #[test]
+    fn who_voted_for_what_is_never_saved_or_listed() {
+        let mut board = board_with_poll();
+
+        assert!(board.vote(1, "attendee".to_string(), 1).is_ok());
+
+        let json = serde_json::to_string(&board).unwrap();
+
+        assert!(!json.contains("attendee"));
+        assert!(json.contains("\"counts\":[0,1]"));
+    }
+
+    #[test]
+    fn votes_need_an_open_poll_and_a_real_option() {
+        let mut board = board_with_poll();
+
+        assert!(board.vote(1, "attendee".to_string(), 2).is_err());
+        assert!(board.vote(2, "attendee".to_string(), 0).is_err());
+
+        board.polls[0].closed_at = Some(1);
+        assert!(board.vote(1, "attendee".to_string(), 0).is_err());
+
+        assert_eq!(board.polls[0].counts, vec![0, 0]);
+        assert!(!board.unsaved_votes);
+    }
+
+    #[test]
+    fn published_polls_hand_back_their_results() {
+        let mut board = board_with_poll();
+
+        assert_eq!(board.vote(1, "attendee".to_string(), 0).map(|results| results.is_some()), Ok(false));
+
+        board.polls[0].published = true;
+
+        let results = board.vote(1, "someone else".to_string(), 1).unwrap().unwrap();
+        assert_eq!(results.counts, vec![1, 1]);
+    }
+
+    #[test]
+    fn polls_need_a_question_and_two_options() {
+        assert!(clean("  ", &["a".to_string(), "b".to_string()]).is_err());
+        assert!(clean("Why?", &["a".to_string(), "  ".to_string()]).is_err());
+
+        let (question, options) = clean(" Why? ", &[" a ".to_string(), "".to_string(), "b".to_string()]).unwrap();
+        assert_eq!(question, "Why?");
+        assert_eq!(options, vec!["a", "b"]);
+    }
+}
@@ -0,0 +1,380 @@
+use std::collections::BTreeMap;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+
+// Bumped whenever a message changes shape. Clients get it in the slidesInfo handshake
+pub const PROTOCOL_VERSION: u32 = 12;
+
+
+// Messages are always JSON text frames. Clients can opt into binary frames for slide previews (?thumbnails=true),
+// the first byte says what the rest of the frame is
+pub const FRAME_THUMBNAIL: u8 = 1; // the slide number (2 bytes, big-endian) followed by a PNG preview
+
+
+// What actually goes over the wire: the message plus its sequence number.
+// Broadcasts are numbered 1, 2, 3... and slidesInfo carries the number of the last broadcast it includes,
+// so a client reconnecting with ?since={seq} gets exactly what it missed.
+// Replies meant for a single client (welcome, pong) and volatile broadcasts (pointer) have no sequence number
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct Envelope {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    #[serde(flatten)]
+    pub message: ServerMessage
+}
+
+
+// Every message the server sends over the WebSocket.
+// The wire shape is {"message_type": "slideAdded", "current_state": {...}}, with only the fields that message needs
+#[derive(Serialize, JsonSchema, Clone)]
+#[serde(tag = "message_type", content = "current_state", rename_all = "camelCase")]
+pub enum ServerMessage {
+    SlidesInfo(SlidesInfo),
+    SlideAdded(SlideAdded),
+    SlideDeleted(SlideDeleted),
+    PresenterPosition(PresenterPosition),
+    Welcome(Welcome),
+    Pong(Pong),
+    Pointer(Pointer),
+    AnnotationAdded(AnnotationAdded),
+    AnnotationCleared(AnnotationCleared),
+    QuestionUpdated(PublicQuestion),
+    QuestionRemoved(QuestionRemoved),
+    QuestionRejected(QuestionRejected),
+    PollOpened(PollInfo),
+    PollClosed(PollClosed),
+    PollResults(PollResults),
+    Reactions(Reactions),
+    NotesPublished(NotesPublished),
+    SlideText(SlideText)
+}
+
+// Sent once, right after a client connects
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct SlidesInfo {
+    pub protocol_version: u32,
+    pub num_slides: u16, // highest slide number handed out so far
+    pub deleted_slides: Vec<u16>,
+    pub presenter_slide: u16, // 0 before the first capture
+    pub annotations: BTreeMap<u16, Vec<Stroke>>, // the presenter's ink, only for slides that have some
+    pub questions: Vec<PublicQuestion>, // the Q&A queue, without the questions the presenter hid
+    pub open_poll: Option<PollInfo>, // the poll the audience can vote in right now
+    pub poll_results: Vec<PollResults>, // results the presenter shared
+    pub notes: BTreeMap<u16, String>, // speaker notes the presenter published as handout notes, by slide
+    pub texts: BTreeMap<u16, String> // what OCR read on each slide, for the ones it has finished
+}
+
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct SlideAdded {
+    pub num_slides: u16 // the new slide is /slides/{num_slides}
+}
+
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct SlideDeleted {
+    pub deleted_slides: Vec<u16> // every slide deleted so far, not just the latest
+}
+
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct PresenterPosition {
+    pub presenter_slide: u16
+}
+
+
+// One pen stroke the presenter drew on a slide. Points and width are fractions of the slide,
+// so the stroke lands in the same place whatever size the slide is shown at
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct Stroke {
+    pub id: u64,
+    pub color: String, // #rrggbb
+    pub width: f64, // as a fraction of the slide width
+    pub points: Vec<[f64; 2]> // [x, y], 0..1 from the top left corner
+}
+
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct AnnotationAdded {
+    pub slide: u16,
+    pub stroke: Stroke
+}
+
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct AnnotationCleared {
+    pub slide: u16 // every stroke on it is gone
+}
+
+
+// A question as the audience sees it. Sent when it's asked and whenever it changes
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct PublicQuestion {
+    pub id: u64,
+    pub slide: Option<u16>, // the slide it was asked about
+    pub text: String,
+    pub author: Option<String>,
+    pub upvotes: usize,
+    pub answered: bool
+}
+
+// The presenter hid the question, so it should disappear from the list
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct QuestionRemoved {
+    pub question_id: u64
+}
+
+// Sent only to the client whose question wasn't accepted
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct QuestionRejected {
+    pub reason: String
+}
+
+
+// The presenter put a question to the audience. Vote with {"message_type": "vote", "poll_id": 1, "option": 0}
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct PollInfo {
+    pub poll_id: u64,
+    pub question: String,
+    pub options: Vec<String>
+}
+
+// Voting is over. Results only follow if the presenter publishes them
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct PollClosed {
+    pub poll_id: u64
+}
+
+// Sent when the presenter publishes the results, and again for every vote while the poll is still open
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct PollResults {
+    pub poll_id: u64,
+    pub question: String,
+    pub options: Vec<String>,
+    pub counts: Vec<usize> // votes per option, in the same order
+}
+
+
+// A slide's handout notes changed. None when the presenter took them back (or emptied them)
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct NotesPublished {
+    pub slide: u16,
+    pub notes: Option<String>
+}
+
+
+// OCR finished reading a slide. Slides it couldn't read never get one
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct SlideText {
+    pub slide: u16,
+    pub text: String
+}
+
+
+// What a remote control can ask for, e.g. {"message_type": "control", "action": "next"}.
+// Only connections opened with the controller key may send these
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlAction {
+    Next, // like the right arrow: the next slide, or a capture when the presenter is already on the latest one
+    Prev, // like the left arrow
+    Capture // like J: capture the screen right now
+}
+
+
+// The reactions the audience can send, e.g. {"message_type": "react", "reaction": "clap", "slide": 3}
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Reaction {
+    ThumbsUp,
+    Heart,
+    Laugh,
+    Surprised,
+    Clap,
+    Confused
+}
+
+// The reactions that came in since the last one of these, by slide. Sent at most twice a second and never replayed
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct Reactions {
+    pub slides: BTreeMap<u16, BTreeMap<Reaction, u64>>
+}
+
+
+// The presenter's mouse pointer while they hold Ctrl, as a fraction of the slide from the top left corner
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct Pointer {
+    pub visible: bool, // false when the presenter let go, x and y are meaningless then
+    pub x: f64,
+    pub y: f64
+}
+
+
+// Sent to a client right after the handshake, with the ID the server knows it by
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct Welcome {
+    pub client_id: u64
+}
+
+// The answer to a ping
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct Pong {
+    pub server_time: u64 // milliseconds since the epoch
+}
+
+
+// Every message a client can send over the WebSocket, e.g. {"message_type": "setDisplayName", "name": "Ana"}
+#[derive(Deserialize, JsonSchema, Clone, Debug)]
+#[serde(tag = "message_type", rename_all = "camelCase")]
+pub enum ClientMessage {
+    Ping,
+    RequestSnapshot,
+    SetDisplayName { name: String },
+    ReportCurrentSlide { slide: u16 },
+    AskQuestion { text: String, slide: Option<u16> },
+    UpvoteQuestion { question_id: u64 },
+    Vote { poll_id: u64, option: usize },
+    React { reaction: Reaction, slide: Option<u16> },
+    Control { action: ControlAction }
+}
+
+
+impl Envelope {
+
+    // A message for one client only, outside the broadcast sequence
+    pub fn direct(message: ServerMessage) -> Self {
+
+        Envelope { seq: None, message }
+    }
+
+    pub fn to_json(&self) -> String {
+
+        serde_json::to_string(self).expect("server messages always serialize")
+    }
+}
+
+
+// A slide preview as a FRAME_THUMBNAIL binary frame
+pub fn thumbnail_frame(slide: u16, png: &[u8]) -> Vec<u8> {
+
+    let mut frame = Vec::with_capacity(png.len() + 3);
+
+    frame.push(FRAME_THUMBNAIL);
+    frame.extend_from_slice(&slide.to_be_bytes());
+    frame.extend_from_slice(png);
+
+    frame
+}
+
+
+// The JSON Schema of a WebSocket message, served at /api/schema so third-party viewers can validate against it
+pub fn schema() -> schemars::Schema {
+
+    schemars::schema_for!(Envelope)
+}
+
+// The same for what clients send, served at /api/schema/client
+pub fn client_schema() -> schemars::Schema {
+
+    schemars::schema_for!(ClientMessage)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn envelopes_carry_the_type_and_sequence() {
+        let added = Envelope { seq: Some(7), message: ServerMessage::SlideAdded(SlideAdded { num_slides: 3 }) };
+
+        assert_eq!(
+            serde_json::to_value(&added).unwrap(),
+            serde_json::json!({ "seq": 7, "message_type": "slideAdded", "current_state": { "num_slides": 3 } })
+        );
+
+        // Replies to one client are outside the sequence
+        let pong = Envelope::direct(ServerMessage::Pong(Pong { server_time: 42 })).to_json();
+        assert_eq!(pong, r#"{"message_type":"pong","current_state":{"server_time":42}}"#);
+    }
+
+    #[test]
+    fn client_messages_parse() {
+        let parse = |json: &str| serde_json::from_str::<ClientMessage>(json);
+
+        assert!(matches!(parse(r#"{"message_type":"ping"}"#), Ok(ClientMessage::Ping)));
+        assert!(matches!(parse(r#"{"message_type":"requestSnapshot"}"#), Ok(ClientMessage::RequestSnapshot)));
+        assert!(matches!(
+            parse(r#"{"message_type":"setDisplayName","name":"Ana"}"#),
+            Ok(ClientMessage::SetDisplayName { name }) if name == "Ana"
+        ));
+        assert!(matches!(
+            parse(r#"{"message_type":"reportCurrentSlide","slide":4}"#),
+            Ok(ClientMessage::ReportCurrentSlide { slide: 4 })
+        ));
+        assert!(matches!(
+            parse(r#"{"message_type":"askQuestion","text":"Why?"}"#),
+            Ok(ClientMessage::AskQuestion { slide: None, .. })
+        ));
+        assert!(matches!(
+            parse(r#"{"message_type":"upvoteQuestion","question_id":2}"#),
+            Ok(ClientMessage::UpvoteQuestion { question_id: 2 })
+        ));
+        assert!(matches!(
+            parse(r#"{"message_type":"vote","poll_id":1,"option":0}"#),
+            Ok(ClientMessage::Vote { poll_id: 1, option: 0 })
+        ));
+        assert!(matches!(
+            parse(r#"{"message_type":"react","reaction":"thumbs_up","slide":2}"#),
+            Ok(ClientMessage::React { reaction: Reaction::ThumbsUp, slide: Some(2) })
+        ));
+        assert!(matches!(
+            parse(r#"{"message_type":"control","action":"next"}"#),
+            Ok(ClientMessage::Control { action: ControlAction::Next })
+        ));
+    }
+
+    #[test]
+    fn malformed_client_messages_are_errors() {
+        for json in [
+            r#"{"message_type":"deleteEverything"}"#,
+            r#"{"message_type":"reportCurrentSlide"}"#,
+            r#"{"message_type":"reportCurrentSlide","slide":-1}"#,
+            r#"{"message_type":"react","reaction":"skull"}"#,
+            r#"{"slide":1}"#,
+            "not json",
+        ] {
+            assert!(serde_json::from_str::<ClientMessage>(json).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn strokes_round_trip() {
+        let stroke = Stroke { id: 1, color: "#ff3f00".into(), width: 0.004, points: vec![[0.1, 0.2], [0.3, 0.4]] };
+
+        let back: Stroke = serde_json::from_str(&serde_json::to_string(&stroke).unwrap()).unwrap();
+
+        assert_eq!(back.id, 1);
+        assert_eq!(back.color, "#ff3f00");
+        assert_eq!(back.width, 0.004);
+        assert_eq!(back.points, vec![[0.1, 0.2], [0.3, 0.4]]);
+    }
+
+    #[test]
+    fn schemas_list_every_message() {
+        let server = serde_json::to_string(&schema()).unwrap();
+        let client = serde_json::to_string(&client_schema()).unwrap();
+
+        for message_type in ["slidesInfo", "slideAdded", "slideDeleted", "pollOpened", "notesPublished", "slideText"] {
+            assert!(server.contains(message_type), "{}", message_type);
+        }
+
+        for message_type in ["ping", "askQuestion", "vote", "react", "control"] {
+            assert!(client.contains(message_type), "{}", message_type);
+        }
+    }
+
+    #[test]
+    fn thumbnail_frames_lead_with_the_slide_number() {
+        let frame = thumbnail_frame(0x0102, b"png");
+
+        assert_eq!(frame, [FRAME_THUMBNAIL, 0x01, 0x02, b'p', b'n', b'g']);
+    }
+}
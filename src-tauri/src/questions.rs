@@ -0,0 +1,348 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::manifest;
+use crate::protocol;
+use crate::search;
+use crate::utility;
+
+
+// Longest question we keep, anything after that is cut off
+const MAX_QUESTION_LENGTH: usize = 500;
+
+// How many unanswered questions one attendee can have in the queue at once, however many tabs they open
+const MAX_OPEN_PER_CLIENT: usize = 5;
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum QuestionStatus {
+    Open,
+    Answered,
+    Hidden // taken off the audience's screens, only the presenter still sees it
+}
+
+// A question from the audience, as the presenter sees it
+#[derive(Serialize, Clone)]
+pub struct Question {
+    pub id: u64,
+    pub slide: Option<u16>, // the slide the client was looking at when it asked
+    pub text: String,
+    pub author: Option<String>, // the client's display name, if it set one
+    pub asked_by: u64, // client ID
+    pub asked_at: u64,
+    pub upvotes: usize,
+    pub status: QuestionStatus,
+    #[serde(skip)]
+    asker: String, // who asked, by attendees::voter_id, for the open question limit
+    #[serde(skip)]
+    voters: HashSet<String> // by attendees::voter_id, so nobody can upvote the same question twice
+}
+
+// Every question of the session, saved as assets/questions.json
+#[derive(Serialize, Default)]
+pub struct QuestionBoard {
+    next_id: u64,
+    questions: Vec<Question>
+}
+
+
+impl Question {
+
+    // What the audience gets to see
+    fn public(&self) -> protocol::PublicQuestion {
+
+        protocol::PublicQuestion {
+            id: self.id,
+            slide: self.slide,
+            text: self.text.clone(),
+            author: self.author.clone(),
+            upvotes: self.upvotes,
+            answered: self.status == QuestionStatus::Answered
+        }
+    }
+}
+
+impl QuestionBoard {
+
+    // The questions the audience can see, for slidesInfo
+    pub fn public(&self) -> Vec<protocol::PublicQuestion> {
+
+        self.questions.iter()
+            .filter(|question| question.status != QuestionStatus::Hidden)
+            .map(Question::public)
+            .collect()
+    }
+
+    // Queues the question under the next ID, unless whoever asked it already has too many waiting
+    fn add(&mut self, mut question: Question) -> Result<Question, &'static str> {
+
+        let open = self.questions.iter()
+            .filter(|queued| queued.asker == question.asker && queued.status == QuestionStatus::Open)
+            .count();
+
+        if open >= MAX_OPEN_PER_CLIENT {
+            return Err("You already have several questions waiting, give the presenter a moment");
+        }
+
+        self.next_id += 1;
+        question.id = self.next_id;
+
+        self.questions.push(question.clone());
+
+        Ok(question)
+    }
+
+    // The question with the upvote counted, or None when it didn't count
+    fn upvote(&mut self, question_id: u64, voter: String) -> Option<protocol::PublicQuestion> {
+
+        let question = self.questions.iter_mut()
+            .find(|question| question.id == question_id && question.status != QuestionStatus::Hidden)?;
+
+        if !question.voters.insert(voter) {
+            return None;
+        }
+
+        question.upvotes += 1;
+
+        Some(question.public())
+    }
+
+    async fn save(&self) {
+
+        let result = match serde_json::to_string_pretty(self) {
+            Ok(json) => tokio::fs::write(questions_path(), json).await,
+            Err(e) => Err(e.into())
+        };
+
+        if let Err(e) = result {
+            eprintln!("Failed to save the questions: {}", e);
+        }
+    }
+}
+
+
+pub fn questions_path() -> PathBuf {
+
+    PathBuf::from("assets").join("questions.json")
+}
+
+
+// Adds a question from a client. The error is sent back to the client as is
+pub async fn ask(
+    current_state: &utility::CurrentSlideState,
+    client_id: u64,
+    text: &str,
+    slide: Option<u16>
+) -> Result<(), &'static str> {
+
+    let text: String = text.trim().chars().take(MAX_QUESTION_LENGTH).collect();
+
+    if text.is_empty() {
+        return Err("The question is empty");
+    }
+
+    let Some((author, asker)) = current_state.clients.lock().await
+        .get(&client_id)
+        .map(|client| (client.display_name.clone(), client.voter.clone()))
+    else {
+        return Err("You're not connected");
+    };
+
+    let question = {
+
+        let mut board = current_state.questions.lock().await;
+
+        let question = board.add(Question {
+            id: 0,
+            slide,
+            text,
+            author,
+            asked_by: client_id,
+            asked_at: manifest::now_millis(),
+            upvotes: 0,
+            status: QuestionStatus::Open,
+            asker,
+            voters: HashSet::new()
+        })?;
+
+        board.save().await;
+
+        question
+    };
+
+    println!("Client {} asked question {}.", client_id, question.id);
+
+    let _ = current_state.broadcast(protocol::ServerMessage::QuestionUpdated(question.public())).await;
+
+    current_state.questions_notify.notify_one();
+
+    Ok(())
+}
+
+
+// One upvote per attendee per question. Hidden questions can't be upvoted
+pub async fn upvote(current_state: &utility::CurrentSlideState, client_id: u64, question_id: u64) {
+
+    let Some(voter) = current_state.clients.lock().await.get(&client_id).map(|client| client.voter.clone()) else {
+        return;
+    };
+
+    let updated = {
+
+        let mut board = current_state.questions.lock().await;
+
+        let Some(updated) = board.upvote(question_id, voter) else {
+            return;
+        };
+
+        board.save().await;
+
+        updated
+    };
+
+    let _ = current_state.broadcast(protocol::ServerMessage::QuestionUpdated(updated)).await;
+
+    current_state.questions_notify.notify_one();
+}
+
+
+// The presenter marks a question answered, hides it, or brings it back
+pub async fn set_status(
+    current_state: &utility::CurrentSlideState,
+    question_id: u64,
+    status: QuestionStatus
+) -> Result<(), String> {
+
+    let question = {
+
+        let mut board = current_state.questions.lock().await;
+
+        let question = board.questions.iter_mut()
+            .find(|question| question.id == question_id)
+            .ok_or("No such question")?;
+
+        question.status = status;
+
+        let question = question.clone();
+
+        board.save().await;
+
+        question
+    };
+
+    let message = match status {
+
+        QuestionStatus::Hidden => protocol::ServerMessage::QuestionRemoved(protocol::QuestionRemoved { question_id }),
+
+        _ => protocol::ServerMessage::QuestionUpdated(question.public())
+    };
+
+    let _ = current_state.broadcast(message).await;
+
+    current_state.questions_notify.notify_one();
+
+    Ok(())
+}
+
+
+// The presenter's queue: open questions first, most upvoted on top, then answered, then hidden
+pub async fn sorted(current_state: &utility::CurrentSlideState) -> Vec<Question> {
+
+    let mut questions = current_state.questions.lock().await.questions.clone();
+
+    questions.sort_by_key(|question| {
+
+        let group = match question.status {
+            QuestionStatus::Open => 0,
+            QuestionStatus::Answered => 1,
+            QuestionStatus::Hidden => 2
+        };
+
+        (group, std::cmp::Reverse(question.upvotes), question.asked_at)
+    });
+
+    questions
+}
+
+
+// Keeps the last session's questions with the rest of it, before a fresh session clears the assets folder
+pub async fn archive(session_id: &str) {
+
+    let folder = search::archived_session_dir(session_id);
+
+    let result = match tokio::fs::create_dir_all(&folder).await {
+        Ok(_) => tokio::fs::copy(questions_path(), folder.join("questions.json")).await.map(|_| ()),
+        Err(e) => Err(e)
+    };
+
+    match result {
+        Ok(_) => println!("Kept the questions of {}.", session_id),
+        // Nobody asked anything
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => eprintln!("Failed to keep the questions of {}: {}", session_id, e)
+    }
+}
+
+
+// A fresh session starts with an empty queue
+pub async fn reset(current_state: &utility::CurrentSlideState) {
+
+    *current_state.questions.lock().await = QuestionBoard::default();
+
+    current_state.questions_notify.notify_one();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn question(client_id: u64, asker: &str) -> Question {
+        Question {
+            id: 0,
+            slide: None,
+            text: "Why?".to_string(),
+            author: None,
+            asked_by: client_id,
+            asked_at: 0,
+            upvotes: 0,
+            status: QuestionStatus::Open,
+            asker: asker.to_string(),
+            voters: HashSet::new()
+        }
+    }
+
+    #[test]
+    fn the_open_question_limit_holds_across_connections() {
+        let mut board = QuestionBoard::default();
+
+        // A new connection (a reload, a second tab) is a new client ID but the same attendee
+        for client_id in 0..MAX_OPEN_PER_CLIENT as u64 {
+            assert!(board.add(question(client_id, "attendee")).is_ok());
+        }
+
+        assert!(board.add(question(99, "attendee")).is_err());
+        assert!(board.add(question(99, "someone else")).is_ok());
+
+        // Once one is answered there's room again
+        board.questions[0].status = QuestionStatus::Answered;
+        assert!(board.add(question(100, "attendee")).is_ok());
+    }
+
+    #[test]
+    fn an_attendee_upvotes_a_question_once() {
+        let mut board = QuestionBoard::default();
+        let id = board.add(question(1, "asker")).unwrap().id;
+
+        assert_eq!(board.upvote(id, "attendee".to_string()).map(|q| q.upvotes), Some(1));
+        assert!(board.upvote(id, "attendee".to_string()).is_none());
+        assert_eq!(board.upvote(id, "someone else".to_string()).map(|q| q.upvotes), Some(2));
+
+        board.questions[0].status = QuestionStatus::Hidden;
+        assert!(board.upvote(id, "a third".to_string()).is_none());
+        assert!(board.upvote(id + 1, "attendee".to_string()).is_none());
+    }
+}
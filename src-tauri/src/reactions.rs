@@ -0,0 +1,264 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::manifest;
+use crate::protocol;
+use crate::search;
+use crate::utility;
+
+
+// Each client gets a bucket of reactions that refills over time. A full bucket allows a quick burst,
+// after that the client is held to the refill rate and anything more is dropped
+const BUCKET_SIZE: f64 = 5.0;
+const REFILL_PER_SECOND: f64 = 2.0;
+
+
+// Reactions by slide
+pub type ReactionCounts = BTreeMap<u16, BTreeMap<protocol::Reaction, u64>>;
+
+
+// A client's bucket, kept with the rest of its ClientInfo
+#[derive(Clone)]
+pub struct RateLimit {
+    tokens: f64,
+    refilled_at: u64 // milliseconds since the epoch
+}
+
+// What the presenter's window gets after every batch
+#[derive(Serialize, Clone)]
+pub struct ReactionUpdate {
+    pub burst: ReactionCounts, // what just came in
+    pub totals: ReactionCounts // the running totals of those slides
+}
+
+
+impl Default for RateLimit {
+
+    fn default() -> Self {
+
+        RateLimit { tokens: BUCKET_SIZE, refilled_at: manifest::now_millis() }
+    }
+}
+
+impl RateLimit {
+
+    // Takes a token if there is one
+    fn allow(&mut self, now: u64) -> bool {
+
+        let elapsed = now.saturating_sub(self.refilled_at) as f64 / 1000.0;
+
+        self.tokens = (self.tokens + elapsed * REFILL_PER_SECOND).min(BUCKET_SIZE);
+        self.refilled_at = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+
+        self.tokens -= 1.0;
+
+        true
+    }
+}
+
+
+// Counts a reaction from a client. Nothing is broadcast here: reactions pile up until the next flush,
+// so a room full of phones still only sends one message every flush interval
+pub async fn react(
+    current_state: &utility::CurrentSlideState,
+    client_id: u64,
+    reaction: protocol::Reaction,
+    slide: Option<u16>
+) {
+
+    let slide = {
+
+        let mut clients = current_state.clients.lock().await;
+
+        let Some(client) = clients.get_mut(&client_id) else {
+            return;
+        };
+
+        if !client.reaction_limit.allow(manifest::now_millis()) {
+            return;
+        }
+
+        // Clients that didn't say which slide they're reacting to are reacting to the one they're on
+        match slide.or(client.current_slide) {
+            Some(slide) => slide,
+            None => return
+        }
+    };
+
+    let exists = current_state.manifest.lock().await
+        .slide(slide)
+        .is_some_and(|entry| entry.deleted_at.is_none());
+
+    if !exists {
+        return;
+    }
+
+    *current_state.pending_reactions.lock().await
+        .entry(slide)
+        .or_default()
+        .entry(reaction)
+        .or_insert(0) += 1;
+}
+
+
+// Adds whatever came in since the last flush to the manifest and passes it on to the audience.
+// Returns what the presenter should see, None if nobody reacted
+pub async fn flush(current_state: &utility::CurrentSlideState) -> Option<ReactionUpdate> {
+
+    let burst = std::mem::take(&mut *current_state.pending_reactions.lock().await);
+
+    if burst.is_empty() {
+        return None;
+    }
+
+    let mut totals = ReactionCounts::new();
+
+    {
+        let mut manifest = current_state.manifest.lock().await;
+
+        for (slide, counts) in &burst {
+
+            let Some(entry) = manifest.slide_mut(*slide) else {
+                continue;
+            };
+
+            for (reaction, count) in counts {
+                *entry.reactions.entry(*reaction).or_insert(0) += count;
+            }
+
+            totals.insert(*slide, entry.reactions.clone());
+        }
+
+        if let Err(e) = manifest.save().await {
+            eprintln!("Failed to save the manifest: {}", e);
+        }
+    }
+
+    // Volatile, a reconnecting client has no use for reactions that already floated by
+    let _ = current_state.broadcast_volatile(protocol::ServerMessage::Reactions(protocol::Reactions {
+        slides: burst.clone()
+    }));
+
+    Some(ReactionUpdate { burst, totals })
+}
+
+
+// Every slide's totals, for the presenter's window when it (re)loads
+pub async fn totals(current_state: &utility::CurrentSlideState) -> ReactionCounts {
+
+    slide_totals(&*current_state.manifest.lock().await)
+}
+
+
+fn slide_totals(session: &manifest::SessionManifest) -> ReactionCounts {
+
+    session.slides.iter()
+        .filter(|slide| slide.deleted_at.is_none() && !slide.reactions.is_empty())
+        .map(|slide| (slide.id, slide.reactions.clone()))
+        .collect()
+}
+
+
+// Keeps the last session's totals with the rest of it as reactions.json, in the same form as get_reaction_totals
+pub async fn archive(session: &manifest::SessionManifest) {
+
+    let totals = slide_totals(session);
+
+    if totals.is_empty() {
+        return;
+    }
+
+    let folder = search::archived_session_dir(&session.session_id);
+
+    let result = match (tokio::fs::create_dir_all(&folder).await, serde_json::to_string_pretty(&totals)) {
+        (Ok(_), Ok(json)) => tokio::fs::write(folder.join("reactions.json"), json).await,
+        (Err(e), _) => Err(e),
+        (_, Err(e)) => Err(e.into())
+    };
+
+    match result {
+        Ok(_) => println!("Kept the reactions of {}.", session.session_id),
+        Err(e) => eprintln!("Failed to keep the reactions of {}: {}", session.session_id, e)
+    }
+}
+
+
+// A fresh session starts a fresh manifest, so only the reactions still waiting for a flush have to go
+pub async fn reset(current_state: &utility::CurrentSlideState) {
+
+    current_state.pending_reactions.lock().await.clear();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_full_bucket_allows_a_burst_then_the_refill_rate() {
+        let mut limit = RateLimit { tokens: BUCKET_SIZE, refilled_at: 1000 };
+
+        for _ in 0..BUCKET_SIZE as usize {
+            assert!(limit.allow(1000));
+        }
+
+        assert!(!limit.allow(1000));
+
+        // Two a second: one after half a second, not two
+        assert!(limit.allow(1500));
+        assert!(!limit.allow(1500));
+    }
+
+    #[test]
+    fn the_bucket_never_holds_more_than_its_size() {
+        let mut limit = RateLimit { tokens: 0.0, refilled_at: 0 };
+
+        let allowed = (0..20).filter(|_| limit.allow(3_600_000)).count();
+
+        assert_eq!(allowed, BUCKET_SIZE as usize);
+    }
+
+    #[test]
+    fn reactions_are_sent_by_their_snake_case_names() {
+        let json = serde_json::to_string(&protocol::Reaction::ThumbsUp).unwrap();
+        assert_eq!(json, r#""thumbs_up""#);
+
+        let parsed: protocol::Reaction = serde_json::from_str(r#""confused""#).unwrap();
+        assert_eq!(parsed, protocol::Reaction::Confused);
+
+        assert!(serde_json::from_str::<protocol::Reaction>(r#""ThumbsUp""#).is_err());
+        assert!(serde_json::from_str::<protocol::Reaction>(r#""angry""#).is_err());
+    }
+
+    #[test]
+    fn totals_leave_out_quiet_and_deleted_slides() {
+        let mut session = manifest::SessionManifest::new();
+
+        for id in 1..=3u16 {
+            let mut slide: manifest::SlideEntry = serde_json::from_value(serde_json::json!({
+                "id": id, "file": format!("slide{}.png", id), "captured_at": 0, "width": 1920, "height": 1080, "thumbnail": null, "deleted_at": null
+            })).unwrap();
+            if id != 2 {
+                slide.reactions.insert(protocol::Reaction::Clap, id as u64);
+            }
+            session.slides.push(slide);
+        }
+
+        session.slides[2].deleted_at = Some(1);
+
+        assert_eq!(serde_json::to_value(slide_totals(&session)).unwrap(), serde_json::json!({ "1": { "clap": 1 } }));
+    }
+
+    #[test]
+    fn counts_serialize_by_slide_then_reaction() {
+        let mut counts = ReactionCounts::new();
+        *counts.entry(3).or_default().entry(protocol::Reaction::Clap).or_insert(0) += 2;
+
+        assert_eq!(serde_json::to_value(&counts).unwrap(), serde_json::json!({ "3": { "clap": 2 } }));
+    }
+}
@@ -0,0 +1,521 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::manifest;
+use crate::utility;
+
+
+// More than this and the query was too vague to be useful anyway
+const MAX_RESULTS: usize = 50;
+
+// How many words of context the snippet keeps on each side of the match
+const SNIPPET_WORDS_BEFORE: usize = 8;
+const SNIPPET_WORDS_AFTER: usize = 12;
+
+// Titles are the first line OCR read, cut off after this many characters
+const MAX_TITLE_LENGTH: usize = 80;
+
+
+// A slide in the index is known by its session and its number
+type DocKey = (String, u16);
+
+// The searchable parts of one slide
+struct Doc {
+    title: String,
+    text: String,
+    notes: String,
+    notes_published: bool
+}
+
+// Every slide of every session we have a manifest for, with the words on it pointing back at the slides.
+// It lives in memory, it's read from the manifests the first time someone searches
+#[derive(Default)]
+pub struct SearchIndex {
+    loaded: bool,
+    sessions: HashMap<String, u64>, // when each session started, newest results come first
+    docs: HashMap<DocKey, Doc>,
+    terms: BTreeMap<String, HashSet<DocKey>> // sorted, so a word can be looked up by its start
+}
+
+// Who's searching. The audience only gets the current session with its published notes,
+// the presenter gets every session we kept, private notes included
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Searcher {
+    Audience,
+    Presenter
+}
+
+// Where the match was found
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchedIn {
+    Text, // what OCR read on the slide
+    Notes
+}
+
+#[derive(Serialize, Clone)]
+pub struct SearchResult {
+    pub session_id: String,
+    pub started_at: u64,
+    pub current_session: bool, // its image is still in the assets folder
+    pub slide: u16,
+    pub title: String,
+    pub snippet: String,
+    pub matched_in: MatchedIn
+}
+
+
+// A copy of every past session's manifest, kept when a fresh session clears the assets folder
+fn sessions_dir() -> PathBuf {
+
+    PathBuf::from("sessions")
+}
+
+
+// What else is kept of a past session (its questions, its reaction totals) goes in a folder next to its manifest
+pub fn archived_session_dir(session_id: &str) -> PathBuf {
+
+    sessions_dir().join(session_id)
+}
+
+
+fn archived_path(session_id: &str) -> PathBuf {
+
+    sessions_dir().join(format!("{}.json", session_id))
+}
+
+
+// Keeps the manifest of the session that's about to be cleared, so its slides can still be found later.
+// Hands it back once it's kept
+pub async fn archive_session() -> Option<manifest::SessionManifest> {
+
+    let json = tokio::fs::read_to_string(manifest::manifest_path()).await.ok()?;
+
+    let Ok(session) = serde_json::from_str::<manifest::SessionManifest>(&json) else {
+        eprintln!("The last session's manifest couldn't be read, it won't be searchable");
+        return None;
+    };
+
+    // Nothing was captured, nothing to find
+    if session.session_id.is_empty() || session.slides.is_empty() {
+        return None;
+    }
+
+    let result = match tokio::fs::create_dir_all(sessions_dir()).await {
+        Ok(_) => tokio::fs::write(archived_path(&session.session_id), json).await,
+        Err(e) => Err(e)
+    };
+
+    match result {
+        Ok(_) => {
+            println!("Kept {} for search.", session.session_id);
+            Some(session)
+        }
+        Err(e) => {
+            eprintln!("Failed to keep {} for search: {}", session.session_id, e);
+            None
+        }
+    }
+}
+
+
+// Changes a slide of a kept session, e.g. when OCR reads it after its session ended
+pub async fn update_archived_slide(
+    current_state: &utility::CurrentSlideState,
+    session_id: &str,
+    slide: u16,
+    change: impl FnOnce(&mut manifest::SlideEntry)
+) {
+
+    let path = archived_path(session_id);
+
+    let session = tokio::fs::read_to_string(&path)
+        .await
+        .ok()
+        .and_then(|json| serde_json::from_str::<manifest::SessionManifest>(&json).ok());
+
+    let Some(mut session) = session else {
+        eprintln!("{} wasn't kept, slide {} can't be updated", session_id, slide);
+        return;
+    };
+
+    let Some(entry) = session.slide_mut(slide) else {
+        return;
+    };
+
+    change(entry);
+
+    let result = match serde_json::to_string_pretty(&session) {
+        Ok(json) => tokio::fs::write(&path, json).await,
+        Err(e) => Err(e.into())
+    };
+
+    if let Err(e) = result {
+        eprintln!("Failed to update slide {} of {}: {}", slide, session_id, e);
+        return;
+    }
+
+    if let Some(entry) = session.slide(slide) {
+        update_slide(current_state, session_id, entry).await;
+    }
+}
+
+
+pub async fn search(current_state: &utility::CurrentSlideState, query: &str, searcher: Searcher) -> Vec<SearchResult> {
+
+    let current_session = current_state.manifest.lock().await.session_id.clone();
+
+    let mut index = current_state.search_index.lock().await;
+
+    index.load().await;
+
+    index.search(query, searcher, &current_session)
+}
+
+
+// Re-indexes a slide after it changed (its text came in, its notes were edited or it was deleted)
+pub async fn update_slide(current_state: &utility::CurrentSlideState, session_id: &str, slide: &manifest::SlideEntry) {
+
+    let mut index = current_state.search_index.lock().await;
+
+    // A first load reads the manifest, which already has the change
+    if !index.loaded {
+        index.load().await;
+        return;
+    }
+
+    let key = (session_id.to_string(), slide.id);
+
+    index.remove(&key);
+
+    if slide.deleted_at.is_none() {
+        index.sessions.entry(session_id.to_string()).or_insert(0);
+        index.insert(key, Doc::new(slide.text.as_deref(), &slide.notes, slide.notes_published));
+    }
+}
+
+
+// Makes sure a new session sorts as the newest one
+pub async fn add_session(current_state: &utility::CurrentSlideState, session_id: &str, started_at: u64) {
+
+    current_state.search_index.lock().await.sessions.insert(session_id.to_string(), started_at);
+}
+
+
+impl Doc {
+
+    fn new(text: Option<&str>, notes: &str, notes_published: bool) -> Self {
+
+        let text = text.unwrap_or_default().to_string();
+
+        let title: String = text.lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .unwrap_or_default()
+            .chars()
+            .take(MAX_TITLE_LENGTH)
+            .collect();
+
+        Doc { title, text, notes: notes.to_string(), notes_published }
+    }
+
+    fn words(&self) -> impl Iterator<Item = String> + '_ {
+
+        words(&self.text).chain(words(&self.notes))
+    }
+}
+
+
+impl SearchIndex {
+
+    // Reads every kept session plus the one in the assets folder, once
+    async fn load(&mut self) {
+
+        if self.loaded {
+            return;
+        }
+
+        self.loaded = true;
+
+        let mut files = vec![manifest::manifest_path()];
+
+        if let Ok(mut entries) = tokio::fs::read_dir(sessions_dir()).await {
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                // The folders hold the rest of each session, only the manifests are searched
+                if entry.path().extension().is_some_and(|extension| extension == "json") {
+                    files.push(entry.path());
+                }
+            }
+        }
+
+        for file in files {
+
+            let Ok(json) = tokio::fs::read_to_string(&file).await else {
+                continue;
+            };
+
+            let session = match serde_json::from_str::<manifest::SessionManifest>(&json) {
+                Ok(session) => session,
+                Err(e) => {
+                    eprintln!("Skipping {:?} in the search index: {}", file, e);
+                    continue;
+                }
+            };
+
+            self.sessions.insert(session.session_id.clone(), session.started_at);
+
+            for slide in session.slides.into_iter().filter(|slide| slide.deleted_at.is_none()) {
+
+                let key = (session.session_id.clone(), slide.id);
+
+                self.remove(&key);
+                self.insert(key, Doc::new(slide.text.as_deref(), &slide.notes, slide.notes_published));
+            }
+        }
+
+        println!("Indexed {} slides from {} sessions for search.", self.docs.len(), self.sessions.len());
+    }
+
+    fn insert(&mut self, key: DocKey, doc: Doc) {
+
+        for word in doc.words() {
+            self.terms.entry(word).or_default().insert(key.clone());
+        }
+
+        self.docs.insert(key, doc);
+    }
+
+    fn remove(&mut self, key: &DocKey) {
+
+        let Some(doc) = self.docs.remove(key) else {
+            return;
+        };
+
+        for word in doc.words() {
+
+            if let Some(keys) = self.terms.get_mut(&word) {
+
+                keys.remove(key);
+
+                if keys.is_empty() {
+                    self.terms.remove(&word);
+                }
+            }
+        }
+    }
+
+    // The slides with a word starting with the term
+    fn lookup(&self, term: &str) -> HashSet<DocKey> {
+
+        self.terms.range(term.to_string()..)
+            .take_while(|(word, _)| word.starts_with(term))
+            .flat_map(|(_, keys)| keys.iter().cloned())
+            .collect()
+    }
+
+    // Every term has to start some word on the slide, so "laten chart" finds "Latency chart".
+    // Speaker notes only count when they're published, unless the presenter is the one searching
+    fn search(&self, query: &str, searcher: Searcher, current_session: &str) -> Vec<SearchResult> {
+
+        let mut terms: Vec<String> = words(query).collect();
+        terms.sort();
+        terms.dedup();
+
+        if terms.is_empty() {
+            return Vec::new();
+        }
+
+        let mut candidates = self.lookup(&terms[0]);
+
+        // Past talks (and whatever notes they had) are the presenter's business
+        if searcher == Searcher::Audience {
+            candidates.retain(|(session_id, _)| session_id == current_session);
+        }
+
+        for term in &terms[1..] {
+            let keys = self.lookup(term);
+            candidates.retain(|key| keys.contains(key));
+        }
+
+        let mut results: Vec<(usize, SearchResult)> = candidates.into_iter()
+            .filter_map(|key| {
+
+                let doc = self.docs.get(&key)?;
+
+                let notes = if searcher == Searcher::Presenter || doc.notes_published { doc.notes.as_str() } else { "" };
+
+                let text_words: Vec<String> = words(&doc.text).collect();
+                let note_words: Vec<String> = words(notes).collect();
+                let title_words: Vec<String> = words(&doc.title).collect();
+
+                let count = |list: &[String], term: &str| list.iter().filter(|word| word.starts_with(term)).count();
+
+                // Private notes may have been what matched, then it isn't a match at all
+                if !terms.iter().all(|term| count(&text_words, term) + count(&note_words, term) > 0) {
+                    return None;
+                }
+
+                let score = terms.iter()
+                    .map(|term| count(&title_words, term) * 3 + count(&text_words, term) + count(&note_words, term))
+                    .sum();
+
+                let (matched_in, snippet) = match snippet(&doc.text, &terms) {
+                    Some(snippet) => (MatchedIn::Text, snippet),
+                    None => (MatchedIn::Notes, snippet(notes, &terms).unwrap_or_default())
+                };
+
+                let (session_id, slide) = key;
+
+                Some((score, SearchResult {
+                    started_at: self.sessions.get(&session_id).copied().unwrap_or(0),
+                    current_session: session_id == current_session,
+                    session_id,
+                    slide,
+                    title: doc.title.clone(),
+                    snippet,
+                    matched_in
+                }))
+            })
+            .collect();
+
+        // Best match first, then the newest talk, then in slide order
+        results.sort_by(|(a_score, a), (b_score, b)| {
+            b_score.cmp(a_score)
+                .then(b.started_at.cmp(&a.started_at))
+                .then(a.session_id.cmp(&b.session_id))
+                .then(a.slide.cmp(&b.slide))
+        });
+
+        results.into_iter()
+            .take(MAX_RESULTS)
+            .map(|(_, result)| result)
+            .collect()
+    }
+}
+
+
+// Lowercase words, split on anything that isn't a letter or a digit
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+
+// A few words around the first match in the text, on one line
+fn snippet(text: &str, terms: &[String]) -> Option<String> {
+
+    let original: Vec<&str> = text.split_whitespace().collect();
+
+    let position = original.iter().position(|word| {
+        words(word).any(|word| terms.iter().any(|term| word.starts_with(term.as_str())))
+    })?;
+
+    let start = position.saturating_sub(SNIPPET_WORDS_BEFORE);
+    let end = (position + SNIPPET_WORDS_AFTER).min(original.len());
+
+    Some(format!(
+        "{}{}{}",
+        if start > 0 { "… " } else { "" },
+        original[start..end].join(" "),
+        if end < original.len() { " …" } else { "" }
+    ))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> SearchIndex {
+        let mut index = SearchIndex { loaded: true, ..Default::default() };
+
+        index.sessions.insert("old".to_string(), 1);
+        index.sessions.insert("now".to_string(), 2);
+
+        index.insert(("old".to_string(), 1), Doc::new(Some("Latency chart\nOld numbers"), "", false));
+        index.insert(("now".to_string(), 1), Doc::new(Some("\n  Latency chart  \np99 went down"), "secret plan", false));
+        index.insert(("now".to_string(), 2), Doc::new(Some("Questions?"), "Ask about latency", true));
+
+        index
+    }
+
+    fn found(results: &[SearchResult]) -> Vec<(&str, u16)> {
+        results.iter().map(|result| (result.session_id.as_str(), result.slide)).collect()
+    }
+
+    #[test]
+    fn words_are_lowercase_and_split_on_punctuation() {
+        let words: Vec<String> = words("Hello, World! p99-latency (ÜBER) 3.5").collect();
+
+        assert_eq!(words, vec!["hello", "world", "p99", "latency", "über", "3", "5"]);
+    }
+
+    #[test]
+    fn snippets_keep_a_few_words_around_the_match() {
+        let text = (1..=40).map(|n| format!("w{}", n)).collect::<Vec<_>>().join(" ");
+
+        assert_eq!(
+            snippet(&text, &["w20".to_string()]).unwrap(),
+            "… w12 w13 w14 w15 w16 w17 w18 w19 w20 w21 w22 w23 w24 w25 w26 w27 w28 w29 w30 w31 …"
+        );
+
+        assert_eq!(snippet("Latency, chart", &["chart".to_string()]).unwrap(), "Latency, chart");
+        assert_eq!(snippet("Latency chart", &["nope".to_string()]), None);
+    }
+
+    #[test]
+    fn titles_are_the_first_line_with_text() {
+        assert_eq!(Doc::new(Some("\n  Latency chart  \np99"), "", false).title, "Latency chart");
+        assert_eq!(Doc::new(None, "", false).title, "");
+    }
+
+    #[test]
+    fn every_term_has_to_start_a_word() {
+        let index = index();
+
+        assert_eq!(found(&index.search("laten chart", Searcher::Presenter, "now")), vec![("now", 1), ("old", 1)]);
+        assert!(index.search("atency", Searcher::Presenter, "now").is_empty());
+        assert!(index.search("latency missing", Searcher::Presenter, "now").is_empty());
+        assert!(index.search("  ,. ", Searcher::Presenter, "now").is_empty());
+    }
+
+    #[test]
+    fn the_audience_only_searches_the_current_session() {
+        let index = index();
+
+        let results = index.search("latency", Searcher::Audience, "now");
+
+        assert!(results.iter().all(|result| result.session_id == "now" && result.current_session));
+        assert_eq!(found(&results), vec![("now", 1), ("now", 2)]);
+    }
+
+    #[test]
+    fn private_notes_are_only_searched_by_the_presenter() {
+        let index = index();
+
+        assert!(index.search("secret", Searcher::Audience, "now").is_empty());
+
+        let results = index.search("secret", Searcher::Presenter, "now");
+        assert_eq!(found(&results), vec![("now", 1)]);
+        assert_eq!(results[0].matched_in, MatchedIn::Notes);
+
+        // Published notes are fair game
+        let results = index.search("ask", Searcher::Audience, "now");
+        assert_eq!(results[0].snippet, "Ask about latency");
+    }
+
+    #[test]
+    fn removed_slides_stop_matching() {
+        let mut index = index();
+
+        index.remove(&("now".to_string(), 1));
+
+        assert!(index.search("p99", Searcher::Presenter, "now").is_empty());
+        assert!(!index.terms.contains_key("p99"));
+        assert_eq!(found(&index.search("chart", Searcher::Presenter, "now")), vec![("old", 1)]);
+    }
+}
@@ -21,34 +21,78 @@ use xcap;
 
 use axum::extract::State;
 
-use tauri::{AppHandle, Emitter};
-use tokio::{net::TcpListener, try_join};
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::net::TcpListener;
 
 use tokio::task; 
     use std::fs; 
 
 use crate::utility;
+use crate::tls;
+use crate::access;
+use crate::api;
+use crate::ocr;
+use crate::search;
+use crate::attendees;
+use crate::manifest;
+use crate::protocol;
+use crate::clients;
+use crate::events;
+use crate::pointer;
+use crate::polls;
+use crate::questions;
+use crate::reactions;
 
 use std::path::{Path, PathBuf};
 
 
 use axum::{
-    http::header::{CONTENT_TYPE, HeaderValue},
+    http::header::{CACHE_CONTROL, CONTENT_TYPE, HeaderValue},
     response::IntoResponse,
 };
 
-use tower_http::services::ServeDir;
+use axum::{
+    extract::{ConnectInfo, Form, Query},
+    http::{HeaderMap, StatusCode, header::{RETRY_AFTER, SET_COOKIE, USER_AGENT}},
+    middleware,
+    response::Redirect,
+    extract::Path as UrlPath,
+    routing::post,
+};
+
+use serde::Deserialize;
+
 
 use rdev::{listen, Event, EventType};
 
 use rdev::EventType::KeyRelease;
 use rdev::Key::{RightArrow, KeyJ, LeftArrow};
 
-use tokio::sync::{Mutex, broadcast};
+use tokio::sync::{Mutex, broadcast, watch};
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use axum::extract::ws::{CloseFrame, close_code};
+
+use axum_server::tls_rustls::RustlsConfig;
+use futures_util::future::try_join_all;
 
 type SharedWebSocketSender = Arc<Mutex<SplitSink<WebSocket, Message>>>;
 
+// How often we ping each WebSocket client, and how long it may stay silent before we drop it
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(45);
+
+// Width of the slide previews, the height follows the screen's aspect ratio
+const THUMBNAIL_WIDTH: u32 = 320;
+
+// How long a single send may take (including waiting for the sink) before the client counts as gone
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Reactions are collected and passed on together this often
+const REACTION_FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+
 
 // This function handles the synchronization, capture, and save.
 async fn take_screenshot_and_save(app_handle: AppHandle, current_state: utility::CurrentSlideState) {
@@ -85,22 +129,58 @@ async fn take_screenshot_and_save(app_handle: AppHandle, current_state: utility:
 
         println!("Screenshot saved.");
 
-        // Send the event to the app frontend as well
-        app_handle.emit("new_slide", slide_number);
+        // A small preview for the slide strip, which clients can also have pushed over the WebSocket
+        let thumbnail_name = manifest::thumbnail_file(slide_number);
+        let thumbnail_height = (image.height() * THUMBNAIL_WIDTH / image.width().max(1)).max(1);
 
-        let message_construct = utility::ServerMessage {
-            message_type: "slideAdded".to_string(),
-            current_state: utility::CurrentState{
-                num_slides: Some(slide_number),
-                deleted_slides: None
-            },
+        let thumbnail = match xcap::image::imageops::thumbnail(&image, THUMBNAIL_WIDTH, thumbnail_height)
+            .save(PathBuf::from("assets").join(&thumbnail_name))
+        {
+            Ok(()) => Some(thumbnail_name),
+
+            Err(e) => {
+                eprintln!("Failed to save the thumbnail: {}", e);
+                None
+            }
         };
 
-        // 1. Convert struct to JSON String
-        let json_string = serde_json::to_string(&message_construct).unwrap();
+        // Record the slide in the session manifest
+        {
+            let mut manifest = current_state.manifest.lock().await;
+
+            manifest.slides.push(manifest::SlideEntry {
+                id: slide_number,
+                file: file_name.clone(),
+                captured_at: manifest::now_millis(),
+                width: image.width(),
+                height: image.height(),
+                thumbnail,
+                annotations: Vec::new(),
+                reactions: Default::default(),
+                notes: String::new(),
+                notes_published: false,
+                text: None,
+                text_status: ocr::TextStatus::Pending,
+                deleted_at: None
+            });
+
+            if let Err(e) = manifest.save().await {
+                eprintln!("Failed to save the manifest: {}", e);
+            }
+        }
+
+        // Read the text on it in the background, for search and screen readers
+        ocr::enqueue(&current_state, slide_number).await;
 
+        // Send the event to the app frontend as well
+        app_handle.emit("new_slide", slide_number);
 
-        match current_state.broadcast_tx.send(json_string) {
+        let message_construct = protocol::ServerMessage::SlideAdded(protocol::SlideAdded {
+            num_slides: slide_number
+        });
+
+
+        match current_state.broadcast(message_construct).await {
         Ok(num_receivers) => {
             println!("Successfully broadcasted message to {} WebSocket clients.", num_receivers);
         }
@@ -117,13 +197,43 @@ async fn take_screenshot_and_save(app_handle: AppHandle, current_state: utility:
 
 async fn handle_key_press(event: Event, current_state: utility::CurrentSlideState, app_handle: AppHandle) {
 
-    let current_state_clone = current_state.clone();
+    // The listener thread outlives the servers, so ignore keys while the server is stopped
+    if !current_state.capture_enabled.load(Ordering::SeqCst) {
+        return;
+    }
 
-    match event.event_type {
+    // Ctrl and mouse moves drive the laser pointer
+    pointer::handle_event(&current_state, event.event_type).await;
 
-        KeyRelease(KeyJ) => {
+    let action = match event.event_type {
 
+        KeyRelease(KeyJ) => {
             println!("Key J pressed!");
+            protocol::ControlAction::Capture
+        }
+
+        KeyRelease(RightArrow) => protocol::ControlAction::Next,
+
+        KeyRelease(LeftArrow) => protocol::ControlAction::Prev,
+
+        _ => return
+    };
+
+    run_action(action, current_state, app_handle).await;
+}
+
+// Everything the presenter can do with the keyboard, also what the remote control's buttons end up calling
+async fn run_action(action: protocol::ControlAction, current_state: utility::CurrentSlideState, app_handle: AppHandle) {
+
+    if !current_state.capture_enabled.load(Ordering::SeqCst) {
+        return;
+    }
+
+    let current_state_clone = current_state.clone();
+
+    match action {
+
+        protocol::ControlAction::Capture => {
 
             tauri::async_runtime::spawn(async move {
 
@@ -131,7 +241,7 @@ async fn handle_key_press(event: Event, current_state: utility::CurrentSlideStat
             });
         }
 
-        KeyRelease(RightArrow) => {
+        protocol::ControlAction::Next => {
 
             // Lock the mutex and free it immediately to prevent Mutex deadlocks
             let slide_number = {
@@ -162,43 +272,109 @@ async fn handle_key_press(event: Event, current_state: utility::CurrentSlideStat
             }
         }
 
-        KeyRelease(LeftArrow) => {
+        protocol::ControlAction::Prev => {
 
             current_state.change_volatile_slide_number(&app_handle, false).await;
         }
-
-        _ => {}
     }
 }
 
-fn http_app() -> Router {
-
-    // Create the service to serve files from the "assets" directory
-    let static_files_service = ServeDir::new("assets");
+fn http_app(current_state: utility::CurrentSlideState) -> Router {
 
     // Create the router
+    // Only the slides of the current session are reachable, nothing else from the assets folder is served
     Router::new()
-        // If the request doesn't match any specific route (like /), 
-        // it falls back to checking the static files directory.
-        // Request to /address.js will look for assets/address.js.
-        .fallback_service(static_files_service.clone())
+        .route("/address.js", get(provide_address))
+        .route("/slides/{id}", get(provide_slide))
+        .route("/slides/{id}/thumbnail", get(provide_thumbnail))
+        // The audience page (and our own windows) ask for /slide3.png
+        .route("/{file}", get(provide_slide_file))
+        .merge(api::api_routes())
+        // Attendees' own bookmarks and notes
+        .merge(attendees::attendee_routes())
+        // For networks that break WebSocket upgrades
+        .merge(events::event_routes())
+        // Layers only wrap what was added before them, so the slides need the access cookie
+        // while / and /unlock (added below) can still show the PIN page
+        .layer(middleware::from_fn_with_state(current_state.clone(), access::require_access))
         .route("/", get(provide_app))
+        // The same slides as plain text, for screen readers and slow connections
+        .route("/text", get(provide_text_view))
+        .route("/unlock", post(submit_pin))
+        // The remote control has its own key, the PIN doesn't apply
+        .route("/controller", get(provide_controller))
+        // Blocked addresses don't even get the PIN page
+        .layer(middleware::from_fn_with_state(current_state.clone(), access::reject_blocked))
+        .with_state(current_state)
 }
 
 fn ws_app(current_state:utility::CurrentSlideState) -> Router {
 
     Router::new()
         .route("/", get(ws_handler))
+        .layer(middleware::from_fn_with_state(current_state.clone(), access::reject_blocked))
         .with_state(current_state)
 }
 
+// A reconnecting client tells us the last sequence number it saw
+#[derive(Deserialize)]
+pub struct ResumeQuery {
+    pub since: Option<u64>
+}
+
+// What a WebSocket client opted into, e.g. /?thumbnails=true
+#[derive(Deserialize)]
+struct StreamOptions {
+    #[serde(default)]
+    thumbnails: bool // push a preview of every new slide right after slideAdded
+}
+
 // The main WebSocket handler function
 async fn ws_handler(
     ws: WebSocketUpgrade,
-    State(current_state): State<utility::CurrentSlideState>
+    State(current_state): State<utility::CurrentSlideState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Query(query): Query<access::AccessQuery>,
+    Query(resume): Query<ResumeQuery>,
+    Query(options): Query<StreamOptions>,
+    Query(control): Query<access::ControllerQuery>,
+    headers: HeaderMap
 ) -> Response {
 
-    ws.on_upgrade(|socket: WebSocket| async move {
+    // The remote control proves itself with its key instead of the PIN
+    let role = match control.controller {
+
+        Some(key) if access::is_controller_key(&current_state, &key).await => clients::ClientRole::Controller,
+
+        Some(_) => {
+            println!("Rejected a remote control from {} with the wrong key.", peer);
+
+            // Slow down anyone trying to guess the key
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+
+        None => clients::ClientRole::Audience
+    };
+
+    // When the session has a PIN, only clients holding the access token get to upgrade
+    if role == clients::ClientRole::Audience && !access::is_allowed(&current_state, peer, &headers, query.token.as_deref()).await {
+
+        println!("Rejected a WebSocket upgrade from {} without a valid token.", peer);
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let user_agent = headers
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+
+    let voter = attendees::voter_id(&current_state.access_secret, &headers, peer);
+
+    let thumbnails = options.thumbnails;
+
+    ws.on_upgrade(move |socket: WebSocket| async move {
 
         // 1. Split the socket into a sink (sender) and a stream (receiver).
         let (sender, mut receiver) = socket.split();
@@ -209,91 +385,249 @@ async fn ws_handler(
         // --- Clone the shared sender for the broadcast task ---
         let broadcast_sender_clone = shared_sender.clone();
         
-        // --- 🎯 FIX: SEND INITIAL SLIDE NUMBER IMMEDIATELY UPON CONNECT ---
-        {
+        // Get the broadcast receiver for fanout.
+        // A client resuming with ?since={seq} only needs what it missed, if we still have it
+        let (mut rx, initial_messages) = current_state.subscribe(resume.since).await;
 
-            // Lock the slide state to get the current number
-            let slide_number = *current_state.slide_number.lock().await; 
+        // --- 🎯 FIX: SEND INITIAL SLIDE NUMBER IMMEDIATELY UPON CONNECT ---
+        let initial_seq = {
 
-            let deleted_slides_number = current_state.deleted_slides.lock().await.clone();
+            let last_sent = initial_messages.last().map(|m| m.seq).unwrap_or(0);
 
+            for initial_message in initial_messages {
 
-            let message_construct = utility::ServerMessage {
-                message_type: "slidesInfo".to_string(),
-                current_state: utility::CurrentState{
-                    num_slides: Some(slide_number),
-                    deleted_slides: Some(deleted_slides_number)
+                if !send_broadcast(&shared_sender, &current_state, initial_message, thumbnails).await {
+                    // If the initial send fails, the connection is likely dropped.
+                    eprintln!("Failed to send initial slide number; client disconnected.");
+                    return; // Exit the upgrade closure early
                 }
+            }
 
-            };
+            last_sent
+        };
 
-            // 1. Convert struct to JSON String
-            let json_string = serde_json::to_string(&message_construct).unwrap();
+        // Give the client an ID so we can keep track of it
+        let (client_id, mut disconnect_rx) = clients::register(&current_state, peer, user_agent, voter, role).await;
 
-            // 2. Wrap it in a WebSocket Text Message
-            let sent_message = Message::Text(json_string.into());
+        {
+            let welcome = protocol::Envelope::direct(protocol::ServerMessage::Welcome(protocol::Welcome { client_id }));
 
-            // Lock the sender to send the initial message
-            let mut sink_lock = shared_sender.lock().await;
+            let _ = send_with_timeout(&shared_sender, Message::Text(welcome.to_json().into())).await;
+        }
 
-            if sink_lock.send(sent_message).await.is_err() {
-                // If the initial send fails, the connection is likely dropped.
-                eprintln!("Failed to send initial slide number; client disconnected.");
-                return; // Exit the upgrade closure early
-            }
-        } // sink_lock is dropped, releasing the sender Mutex.
+        let snapshot_state = current_state.clone();
 
-        // Get the broadcast receiver for fanout
-        let mut rx = current_state.broadcast_tx.subscribe();
+        // Get notified when the presenter stops the server
+        let mut shutdown_rx = current_state.shutdown_tx.subscribe();
         
         // 3. CONCURRENT TASK (Outgoing: Broadcast Listener)
-        tauri::async_runtime::spawn(async move {
+        // It ends when a send fails or times out, and the main loop below ends the connection when it does
+        let mut broadcast_task = tauri::async_runtime::spawn(async move {
             let sender = broadcast_sender_clone; 
 
+            // Everything up to this sequence number was already sent (by a snapshot or the replay)
+            let mut covered_seq = initial_seq;
+
             loop {
                 match rx.recv().await {
                     Ok(msg) => {
-                        let ws_message = Message::Text(msg.into());
-                        let mut sink_lock = sender.lock().await;
-                        if sink_lock.send(ws_message).await.is_err() {
+
+                        if msg.already_sent(covered_seq) {
+                            continue;
+                        }
+
+                        if !send_broadcast(&sender, &snapshot_state, msg, thumbnails).await {
                             break; 
                         }
                     }
                     // Handle lagging separately so the client doesn't get kicked 
-                    // just because they were a millisecond slow once
+                    // just because they were a millisecond slow once.
+                    // They missed something though, so bring them up to date with a fresh snapshot
                     Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
-                        eprintln!("Client lagged behind by {} messages", skipped);
-                        continue; // Keep the loop alive
+                        eprintln!("Client lagged behind by {} messages, sending a snapshot", skipped);
+
+                        let snapshot = snapshot_state.snapshot().await;
+                        covered_seq = snapshot.seq;
+
+                        if !send_with_timeout(&sender, Message::Text(snapshot.json.into())).await {
+                            break;
+                        }
                     }
                     Err(_) => break, // Channel closed
                 }
             }
         });
 
+        // Ping the client every so often, and give up on it when it stops answering.
+        // Phones that go to sleep often leave the TCP connection half open, so we'd never notice otherwise
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+        let mut last_heard = Instant::now();
+
         // 4. MAIN LOOP (Incoming: Client Listener - Simplified)
         // We only listen for close messages or errors to gracefully clean up.
         // All "join" logic is removed.
-        while let Some(msg_result) = receiver.next().await { 
-            match msg_result {
-                Ok(Message::Close(_)) | Err(_) => {
-                    // Connection closed by the client or stream error occurred.
-                    println!("WebSocket client closed connection.");
-                    break;
+        let reason = loop {
+
+            tokio::select! {
+
+                msg_result = receiver.next() => {
+
+                    // Anything from the client, pongs included, proves it's still there
+                    last_heard = Instant::now();
+
+                    match msg_result {
+                        Some(Ok(Message::Close(_))) | None => {
+                            // Connection closed by the client
+                            break clients::DisconnectReason::ClientClosed;
+                        }
+                        Some(Err(e)) => {
+                            eprintln!("WebSocket client {} connection error: {}", client_id, e);
+                            break clients::DisconnectReason::ConnectionError;
+                        }
+                        Some(Ok(Message::Text(text))) => {
+
+                            match serde_json::from_str::<protocol::ClientMessage>(&text) {
+
+                                Ok(client_message) => {
+
+                                    // Some messages (ping, snapshot requests) get an answer just for this client
+                                    if let Some(reply) = clients::handle_message(&current_state, client_id, client_message).await {
+
+                                        if !send_with_timeout(&shared_sender, Message::Text(reply.into())).await {
+                                            break clients::DisconnectReason::SendFailed;
+                                        }
+                                    }
+                                }
+
+                                Err(e) => eprintln!("Ignoring a malformed message from client {}: {}", client_id, e)
+                            }
+                        }
+                        // Ignore all other incoming messages (Binary, Ping/Pong)
+                        _ => {}
+                    }
+                }
+
+                _ = heartbeat.tick() => {
+
+                    if last_heard.elapsed() > HEARTBEAT_TIMEOUT {
+                        break clients::DisconnectReason::HeartbeatTimeout;
+                    }
+
+                    if !send_with_timeout(&shared_sender, Message::Ping(Vec::new().into())).await {
+                        break clients::DisconnectReason::SendFailed;
+                    }
+                }
+
+                // The broadcast task only stops on its own when it couldn't deliver something
+                _ = &mut broadcast_task => {
+                    break clients::DisconnectReason::SendFailed;
+                }
+
+                Some(disconnect) = disconnect_rx.recv() => {
+
+                    // The presenter removed (or blocked) this client
+                    let close_message = Message::Close(Some(CloseFrame {
+                        code: disconnect.code,
+                        reason: disconnect.reason.into(),
+                    }));
+
+                    close_socket(&shared_sender, close_message).await;
+
+                    break clients::DisconnectReason::from(&disconnect);
+                }
+
+                _ = wait_for_shutdown(&mut shutdown_rx) => {
+
+                    // The server is stopping, so say goodbye properly instead of just dropping the socket
+                    let close_message = Message::Close(Some(CloseFrame {
+                        code: close_code::AWAY,
+                        reason: "Server is shutting down".into(),
+                    }));
+
+                    close_socket(&shared_sender, close_message).await;
+
+                    break clients::DisconnectReason::ServerShutdown;
                 }
-                // Ignore all other incoming messages (Text, Binary, Ping/Pong)
-                _ => {}
             }
-        }
+        };
+
+        // Otherwise it would sit on the broadcast channel (and the sink) until the next send fails
+        broadcast_task.abort();
+
+        clients::unregister(&current_state, client_id, reason).await;
     })
 }
 
 
+// Sends a message unless the client is so far behind that the send (or getting the sink) takes too long.
+// Returns false when the message didn't go out, which means the connection is as good as dead
+async fn send_with_timeout(sender: &SharedWebSocketSender, message: Message) -> bool {
+
+    let send = async {
+        let mut sink_lock = sender.lock().await;
+        sink_lock.send(message).await
+    };
+
+    matches!(tokio::time::timeout(SEND_TIMEOUT, send).await, Ok(Ok(())))
+}
+
+
+// Sends a broadcast, followed by the new slide's preview for clients that asked for thumbnails.
+// A missing preview is skipped, the client simply fetches the full image
+async fn send_broadcast(
+    sender: &SharedWebSocketSender,
+    current_state: &utility::CurrentSlideState,
+    message: utility::Broadcast,
+    thumbnails: bool
+) -> bool {
+
+    if !send_with_timeout(sender, Message::Text(message.json.into())).await {
+        return false;
+    }
+
+    let Some(slide) = message.new_slide.filter(|_| thumbnails) else {
+        return true;
+    };
+
+    let thumbnail = current_state.manifest.lock().await
+        .slide(slide)
+        .filter(|entry| entry.deleted_at.is_none())
+        .and_then(|entry| entry.thumbnail.clone());
+
+    match thumbnail {
+
+        Some(file) => match tokio::fs::read(PathBuf::from("assets").join(file)).await {
+
+            Ok(png) => send_with_timeout(sender, Message::Binary(protocol::thumbnail_frame(slide, &png).into())).await,
+
+            Err(_) => true
+        },
+
+        None => true
+    }
+}
+
+
+// Sends the close frame and closes our side, without waiting forever on a client that isn't reading
+async fn close_socket(sender: &SharedWebSocketSender, close_message: Message) {
+
+    if send_with_timeout(sender, close_message).await {
+        let _ = tokio::time::timeout(SEND_TIMEOUT, async { sender.lock().await.close().await }).await;
+    }
+}
+
+
+// Resolves once the shutdown flag is raised (or the sender is gone)
+pub async fn wait_for_shutdown(shutdown_rx: &mut watch::Receiver<bool>) {
+
+    let _ = shutdown_rx.wait_for(|stop| *stop).await;
+}
+
+
 pub async fn setup_server(app_handle: AppHandle, current_state: utility::CurrentSlideState) {
 
     let extracted_current_state = current_state.clone();
 
-    let current_state_clone = extracted_current_state.clone();
-
     // Get the host ip address of the system
     let host_ip: IpAddr = match list_afinet_netifas() {
         Ok(network_interfaces) => {
@@ -309,6 +643,92 @@ pub async fn setup_server(app_handle: AppHandle, current_state: utility::Current
     };
 
 
+    // Remembered so the presenter's own machine is never asked for the PIN
+    *current_state.host_ip.lock().await = Some(host_ip);
+
+    // A session with a PIN keeps the same one across restarts
+    let session_pin = {
+
+        let mut lock = current_state.session_pin.lock().await;
+
+        if current_state.require_pin.load(Ordering::SeqCst) {
+
+            if lock.is_none() {
+                *lock = Some(access::generate_pin());
+            }
+
+        } else {
+            *lock = None;
+        }
+
+        lock.clone()
+    };
+
+
+    // In TLS mode both servers share a self-signed certificate, created on first use and cached afterwards
+    let use_tls = current_state.use_tls.load(Ordering::SeqCst);
+
+    let tls_material = if use_tls {
+
+        let cert_dir = match app_handle.path().app_data_dir() {
+
+            Ok(dir) => dir.join("tls"),
+
+            Err(_) => {
+
+                app_handle.emit("server_start_failure", ());
+
+                println!("Could not find a place to keep the certificate!");
+                return;
+            }
+        };
+
+        match task::spawn_blocking(move || tls::load_or_create_certificate(&cert_dir, host_ip)).await {
+
+            Ok(Ok(material)) => Some(material),
+
+            Ok(Err(e)) => {
+
+                app_handle.emit("server_start_failure", ());
+
+                println!("Could not load the certificate: {}", e);
+                return;
+            }
+
+            Err(_) => {
+
+                app_handle.emit("server_start_failure", ());
+
+                println!("The certificate task failed!");
+                return;
+            }
+        }
+
+    } else {
+        None
+    };
+
+    let tls_config = match &tls_material {
+
+        Some(material) => match RustlsConfig::from_pem(material.cert_pem.clone().into_bytes(), material.key_pem.clone().into_bytes()).await {
+
+            Ok(config) => Some(config),
+
+            Err(_) => {
+
+                app_handle.emit("server_start_failure", ());
+
+                println!("Could not set up TLS!");
+                return;
+            }
+        },
+
+        None => None
+    };
+
+    let (http_scheme, ws_scheme) = if use_tls { ("https", "wss") } else { ("http", "ws") };
+
+
     // Set the port address to 0 to let the OS decide the port number for us
     let port: u16 = 0;
 
@@ -336,12 +756,33 @@ pub async fn setup_server(app_handle: AppHandle, current_state: utility::Current
     println!("The http server was started at {}", http_addr_str);
     
 
-    let http_server = async move {
+    let http_server = serve_app(http_listener, http_app(current_state.clone()), tls_config.clone(), current_state.shutdown_tx.subscribe());
+
+
+    // Our own webview won't trust a self-signed certificate, so in TLS mode the presenter's
+    // windows load the slides over plain HTTP on the loopback interface instead
+    let local_listener = if use_tls {
+
+        match TcpListener::bind(SocketAddr::new(IpAddr::from([127, 0, 0, 1]), port)).await {
 
+            Ok(l) => Some(l),
 
-        serve(http_listener, http_app().into_make_service())
-            .await
-            .map_err(|e| format!("HTTP Server Error: {}", e))
+            Err(_) => {
+
+                app_handle.emit("server_start_failure", ());
+
+                println!("Could not create the local http server!");
+                return;
+            }
+        }
+
+    } else {
+        None
+    };
+
+    let local_url = match &local_listener {
+        Some(l) => format!("http://{}", l.local_addr().unwrap()),
+        None => format!("http://{}", http_addr_str)
     };
 
 
@@ -362,68 +803,320 @@ pub async fn setup_server(app_handle: AppHandle, current_state: utility::Current
     let ws_addr_str = ws_listener.local_addr().unwrap().to_string();
 
 
-    let file_content = format!("export const webSocketAddress = \"ws://{}\";\n", ws_addr_str);
-    let file_path = PathBuf::from("assets").join("address.js");
+    // The audience page imports this from /address.js
+    *current_state.ws_address.lock().await = Some(format!("{}://{}", ws_scheme, ws_addr_str));
+
     let file_dir = PathBuf::from("assets");
 
+    // Only a fresh session starts from an empty folder, a restart keeps the slides captured so far
+    let fresh_session = *current_state.slide_number.lock().await == 0;
+
+    // The last talk's slides go away with the folder, but its text stays searchable and its questions and reactions are kept.
+    // Slides whose text wasn't read yet keep a copy of their image for the OCR worker
+    if fresh_session {
+        if let Some(session) = search::archive_session().await {
+            ocr::keep_waiting(&session).await;
+            questions::archive(&session.session_id).await;
+            reactions::archive(&session).await;
+        }
+    }
+
 
     let write_result = task::spawn_blocking(move || {
 
-        match fs::remove_dir_all(&file_dir) {
-            Ok(_) => {
-                println!("✅ Successfully removed old directory: {:?}", &file_dir);
-            },
-            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
-                // This is the expected and safe case if the directory is missing
-                println!("ℹ️ Directory {:?} not found, proceeding to create.", &file_dir);
-            },
-            Err(e) => {
-                // Fail if the directory exists but we can't remove it (e.g., permissions)
-                return Err(e);
+        if fresh_session {
+
+            match fs::remove_dir_all(&file_dir) {
+                Ok(_) => {
+                    println!("✅ Successfully removed old directory: {:?}", &file_dir);
+                },
+                Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    // This is the expected and safe case if the directory is missing
+                    println!("ℹ️ Directory {:?} not found, proceeding to create.", &file_dir);
+                },
+                Err(e) => {
+                    // Fail if the directory exists but we can't remove it (e.g., permissions)
+                    return Err(e);
+                }
             }
         }
 
 
         fs::create_dir_all(&file_dir)?;
-        fs::write(&file_path, file_content)?;
         Ok::<(), std::io::Error>(())
 
 
     }).await;
 
+    // A fresh session gets a fresh manifest, a restart keeps writing to the same one
+    {
+        let mut manifest = current_state.manifest.lock().await;
+
+        if fresh_session {
+            *manifest = manifest::SessionManifest::new();
+            search::add_session(&current_state, &manifest.session_id, manifest.started_at).await;
+            questions::reset(&current_state).await;
+            polls::reset(&current_state).await;
+            reactions::reset(&current_state).await;
+        }
+
+        if let Ok(Ok(_)) = write_result {
+
+            if let Err(e) = manifest.save().await {
+                eprintln!("Failed to save the manifest: {}", e);
+            }
+        }
+    }
+
     match write_result {
 
-        Ok(Ok(_)) => println!("✅ address.js written successfully."),
+        Ok(Ok(_)) => println!("✅ assets folder is ready."),
 
         _ => {
             // Log the error and consider this a fatal setup failure
-            println!("Fatal: Failed to write address.js file.");
+            println!("Fatal: Failed to prepare the assets folder.");
             app_handle.emit("server_start_failure", ());
             return;
         }
     }
     
 
-    let ws_server = async move {
+    let ws_server = serve_app(ws_listener, ws_app(extracted_current_state.clone()), tls_config, current_state.shutdown_tx.subscribe());
 
-        serve(ws_listener, ws_app(extracted_current_state.clone()).into_make_service())
-            .await
-            .map_err(|e| format!("HTTP Server Error: {}", e))
-    };
+    let mut servers = vec![tokio::spawn(http_server), tokio::spawn(ws_server)];
+
+    if let Some(listener) = local_listener {
+        servers.push(tokio::spawn(serve_app(listener, http_app(current_state.clone()), None, current_state.shutdown_tx.subscribe())));
+    }
 
-    app_handle.emit("server_ready", http_addr_str);
+    app_handle.emit("server_ready", utility::ServerInfo {
+        url: format!("{}://{}", http_scheme, http_addr_str),
+        address: http_addr_str,
+        local_url,
+        fingerprint: tls_material.map(|m| m.fingerprint),
+        pin: session_pin
+    });
 
     println!("The ws server was started at {}", ws_addr_str);
 
-    let app_handle_clone = app_handle.clone();
+    start_key_listener(app_handle.clone(), current_state.clone());
+
+    start_dashboard_updates(app_handle.clone(), current_state.clone());
+    start_question_updates(app_handle.clone(), current_state.clone());
+    start_poll_updates(app_handle.clone(), current_state.clone());
+    start_reaction_updates(app_handle.clone(), current_state.clone());
+    ocr::start_worker(app_handle.clone(), current_state.clone());
+
+    current_state.capture_enabled.store(true, Ordering::SeqCst);
+
+    let result = try_join_all(servers).await;
+
+    // Whatever happened, the keys should no longer trigger captures
+    current_state.capture_enabled.store(false, Ordering::SeqCst);
+
+    if let Err(_) = result
+    {
+
+        println!(" A server crashed unexpectedly.");
+        app_handle.emit("server_crash", ());
+
+    }
+    else
+    {
+        println!("Both servers shut down gracefully.");
+        app_handle.emit("server_stopped", ());
+    }
+    
+}
+
+
+// Serves the app over plain HTTP, or over TLS when a config is given, until the shutdown flag is raised
+async fn serve_app(listener: TcpListener, app: Router, tls_config: Option<RustlsConfig>, mut shutdown_rx: watch::Receiver<bool>) -> Result<(), String> {
+
+    match tls_config {
+
+        None => {
+
+            serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+                .with_graceful_shutdown(async move { wait_for_shutdown(&mut shutdown_rx).await })
+                .await
+                .map_err(|e| format!("HTTP Server Error: {}", e))
+        }
+
+        Some(config) => {
+
+            let handle = axum_server::Handle::new();
+            let handle_clone = handle.clone();
+
+            tokio::spawn(async move {
+                wait_for_shutdown(&mut shutdown_rx).await;
+                handle_clone.graceful_shutdown(Some(std::time::Duration::from_secs(5)));
+            });
+
+            let std_listener = listener.into_std().map_err(|e| format!("HTTPS Server Error: {}", e))?;
+
+            axum_server::from_tcp_rustls(std_listener, config)
+                .handle(handle)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+                .map_err(|e| format!("HTTPS Server Error: {}", e))
+        }
+    }
+}
+
+
+// Keeps the presenter's dashboard in sync with who is connected, until the server stops
+fn start_dashboard_updates(app_handle: AppHandle, current_state: utility::CurrentSlideState) {
+
+    let mut shutdown_rx = current_state.shutdown_tx.subscribe();
+
+    tokio::spawn(async move {
+
+        loop {
+
+            tokio::select! {
+
+                _ = current_state.clients_notify.notified() => {
+
+                    app_handle.emit("clients_changed", clients::list(&current_state).await);
+
+                    // Phones report their slide often, so bunch the updates up a little
+                    tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+                }
+
+                _ = wait_for_shutdown(&mut shutdown_rx) => break
+            }
+        }
+
+        // The clients are being disconnected, so the dashboard can be cleared right away
+        app_handle.emit("clients_changed", Vec::<clients::ClientInfo>::new());
+    });
+}
+
+
+// Keeps the presenter's Q&A queue in sync, the same way as the dashboard.
+// The questions stay after the server stops, they're still worth going through
+fn start_question_updates(app_handle: AppHandle, current_state: utility::CurrentSlideState) {
+
+    let mut shutdown_rx = current_state.shutdown_tx.subscribe();
+
+    tokio::spawn(async move {
+
+        loop {
+
+            tokio::select! {
+
+                _ = current_state.questions_notify.notified() => {
+
+                    app_handle.emit("questions_changed", questions::sorted(&current_state).await);
+
+                    // Upvotes can come in bursts
+                    tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+                }
+
+                _ = wait_for_shutdown(&mut shutdown_rx) => break
+            }
+        }
+    });
+}
+
+
+// Keeps the presenter's live poll results in sync, same as the Q&A queue, and saves the votes in between
+fn start_poll_updates(app_handle: AppHandle, current_state: utility::CurrentSlideState) {
+
+    let mut shutdown_rx = current_state.shutdown_tx.subscribe();
+
+    tokio::spawn(async move {
+
+        loop {
+
+            tokio::select! {
+
+                _ = current_state.polls_notify.notified() => {
+
+                    app_handle.emit("polls_changed", polls::list(&current_state).await);
+
+                    polls::save_votes(&current_state).await;
+
+                    // A popular poll gets its votes all at once
+                    tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+                }
+
+                _ = wait_for_shutdown(&mut shutdown_rx) => {
+
+                    // Votes from the last moments before the server stopped
+                    polls::save_votes(&current_state).await;
+                    break;
+                }
+            }
+        }
+    });
+}
+
+
+// Passes the audience's reactions on in batches, to the audience and to the presenter's window
+fn start_reaction_updates(app_handle: AppHandle, current_state: utility::CurrentSlideState) {
+
+    let mut shutdown_rx = current_state.shutdown_tx.subscribe();
+
+    tokio::spawn(async move {
+
+        let mut interval = tokio::time::interval(REACTION_FLUSH_INTERVAL);
+
+        loop {
+
+            tokio::select! {
+
+                _ = interval.tick() => {
+
+                    if let Some(update) = reactions::flush(&current_state).await {
+                        app_handle.emit("reactions", update);
+                    }
+                }
+
+                _ = wait_for_shutdown(&mut shutdown_rx) => break
+            }
+        }
+
+        // Whatever came in last still belongs in the totals
+        if let Some(update) = reactions::flush(&current_state).await {
+            app_handle.emit("reactions", update);
+        }
+    });
+}
+
+
+// rdev's listen() blocks forever and can't be cancelled, so the thread is spawned once per process
+// and stopping the server only flips capture_enabled off
+fn start_key_listener(app_handle: AppHandle, current_state: utility::CurrentSlideState) {
+
+    if current_state.key_listener_started.swap(true, Ordering::SeqCst) {
+        return;
+    }
 
     let (tx, mut rx) = mpsc::unbounded_channel();
 
+    // The remote control's actions go through the same worker, so they queue up with the key presses
+    let (control_tx, mut control_rx) = mpsc::unbounded_channel();
+    let _ = current_state.control_tx.set(control_tx);
+
+    let worker_state = current_state.clone();
+    let worker_app = app_handle.clone();
+
     // 2. Start the Async "Worker"
     tokio::spawn(async move {
-        while let Some((event, state, app)) = rx.recv().await {
-            // This is where your async function is finally called and awaited
-            handle_key_press(event, state, app).await;
+        loop {
+            tokio::select! {
+                // This is where your async function is finally called and awaited
+                Some((event, state, app)) = rx.recv() => handle_key_press(event, state, app).await,
+
+                Some(action) = control_rx.recv() => {
+                    println!("Remote control: {:?}", action);
+                    run_action(action, worker_state.clone(), worker_app.clone()).await;
+                }
+
+                else => break
+            }
         }
     });
 
@@ -432,33 +1125,413 @@ pub async fn setup_server(app_handle: AppHandle, current_state: utility::Current
         let callback = move |event: Event| {
             // We just send the data and keep moving. 
             // This takes almost zero time, so the keyboard doesn't lag.
-            let _ = tx.send((event, current_state_clone.clone(), app_handle_clone.clone()));
+            let _ = tx.send((event, current_state.clone(), app_handle.clone()));
         };
 
         if let Err(error) = listen(callback) {
             println!("Error: {:?}", error)
         }
     });
+}
 
-    if let Err(_) = try_join!(tokio::spawn(http_server), tokio::spawn(ws_server))
-    {
 
-        println!(" A server crashed unexpectedly.");
-        app_handle.emit("server_crash", ());
 
+async fn provide_app(
+    State(current_state): State<utility::CurrentSlideState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Query(query): Query<access::AccessQuery>,
+    headers: HeaderMap
+) -> Response {
+    // This loads the file content INTO the binary during compilation
+    const HTML: &str = include_str!("../index.html");
+
+    provide_audience_page(&current_state, peer, query, &headers, HTML, AudienceView::Slides).await
+}
+
+
+async fn provide_text_view(
+    State(current_state): State<utility::CurrentSlideState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Query(query): Query<access::AccessQuery>,
+    headers: HeaderMap
+) -> Response {
+
+    const TEXT_HTML: &str = include_str!("../text.html");
+
+    provide_audience_page(&current_state, peer, query, &headers, TEXT_HTML, AudienceView::Text).await
+}
+
+
+// The pages the audience can join on. The PIN page sends them back to the one they asked for
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+enum AudienceView {
+    #[default]
+    Slides,
+    Text
+}
+
+impl AudienceView {
+
+    fn path(self) -> &'static str {
+
+        match self {
+            AudienceView::Slides => "/",
+            AudienceView::Text => "/text"
+        }
     }
-    else
-    {
-        println!("Both servers shut down gracefully.");
+}
+
+
+async fn provide_audience_page(
+    current_state: &utility::CurrentSlideState,
+    peer: SocketAddr,
+    query: access::AccessQuery,
+    headers: &HeaderMap,
+    html: &'static str,
+    view: AudienceView
+) -> Response {
+
+    if access::is_allowed(current_state, peer, headers, None).await {
+
+        // Handed out here, so the page's WebSocket already carries it when it votes or asks something
+        let (_, cookie) = attendees::identify(current_state, headers);
+
+        return attendees::with_cookie(cookie, Html(html).into_response());
+    }
+
+    match query.pin {
+
+        // Scanning a QR code with the PIN embedded joins in one step
+        Some(pin) => unlock(current_state, peer.ip(), &pin, view).await,
+
+        None => Html(pin_page("", view)).into_response()
     }
-    
 }
 
 
+// The remote control page, only for whoever has the controller key (it's in the controller QR code)
+async fn provide_controller(
+    State(current_state): State<utility::CurrentSlideState>,
+    Query(control): Query<access::ControllerQuery>
+) -> Response {
 
-async fn provide_app() -> Html<&'static str> {
-    // This loads the file content INTO the binary during compilation
-    const HTML: &str = include_str!("../index.html");
-    
-    Html(HTML)
-}
\ No newline at end of file
+    const CONTROLLER_HTML: &str = include_str!("../controller.html");
+
+    let Some(key) = control.key else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+
+    if !access::is_controller_key(&current_state, &key).await {
+
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+        return (StatusCode::UNAUTHORIZED, "This remote control link is no longer valid, scan the presenter's controller QR code again").into_response();
+    }
+
+    let Some(ws_address) = current_state.ws_address.lock().await.clone() else {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+
+    // The key is hex, so it can go into the page as is
+    let page = CONTROLLER_HTML
+        .replace("{{ws_address}}", &ws_address)
+        .replace("{{key}}", &key);
+
+    ([(CACHE_CONTROL, HeaderValue::from_static("no-store"))], Html(page)).into_response()
+}
+
+
+// Generated from the running WebSocket server instead of being a file on disk
+async fn provide_address(State(current_state): State<utility::CurrentSlideState>) -> Response {
+
+    match current_state.ws_address.lock().await.clone() {
+
+        Some(address) => (
+            [(CONTENT_TYPE, HeaderValue::from_static("text/javascript"))],
+            format!("export const webSocketAddress = \"{}\";\n", address)
+        ).into_response(),
+
+        None => StatusCode::SERVICE_UNAVAILABLE.into_response()
+    }
+}
+
+
+// ?annotated=true asks for the slide with the presenter's ink on it (for exports)
+#[derive(Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    annotated: bool
+}
+
+
+async fn provide_slide(
+    State(current_state): State<utility::CurrentSlideState>,
+    UrlPath(id): UrlPath<u16>,
+    Query(export): Query<ExportQuery>
+) -> Response {
+
+    let file = slide_file(&current_state, id, export.annotated).await;
+
+    serve_slide(&current_state, id, file).await
+}
+
+
+async fn provide_thumbnail(
+    State(current_state): State<utility::CurrentSlideState>,
+    UrlPath(id): UrlPath<u16>
+) -> Response {
+
+    serve_slide(&current_state, id, manifest::thumbnail_file(id)).await
+}
+
+
+// The legacy /slide{id}.png form, anything else is simply not there
+async fn provide_slide_file(
+    State(current_state): State<utility::CurrentSlideState>,
+    UrlPath(file): UrlPath<String>,
+    Query(export): Query<ExportQuery>
+) -> Response {
+
+    let id = file
+        .strip_prefix("slide")
+        .and_then(|rest| rest.strip_suffix(".png"))
+        .and_then(|number| number.parse::<u16>().ok());
+
+    match id {
+        Some(id) => {
+            let file = slide_file(&current_state, id, export.annotated).await;
+            serve_slide(&current_state, id, file).await
+        }
+        None => StatusCode::NOT_FOUND.into_response()
+    }
+}
+
+
+// The slide as captured, or its annotated copy when that's asked for and there is any ink on it
+async fn slide_file(current_state: &utility::CurrentSlideState, id: u16, annotated: bool) -> String {
+
+    let has_ink = annotated && current_state.manifest.lock().await
+        .slide(id)
+        .is_some_and(|slide| !slide.annotations.is_empty());
+
+    if has_ink { manifest::annotated_file(id) } else { format!("slide{}.png", id) }
+}
+
+
+// Only slides (and their previews) captured in this session are served: deleted ones are 410 Gone, unknown ones 404
+async fn serve_slide(current_state: &utility::CurrentSlideState, id: u16, file: String) -> Response {
+
+    let slide_number = *current_state.slide_number.lock().await;
+
+    if id == 0 || id > slide_number {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    if current_state.deleted_slides.lock().await.contains(&id) {
+        return StatusCode::GONE.into_response();
+    }
+
+    match tokio::fs::read(PathBuf::from("assets").join(file)).await {
+
+        Ok(bytes) => ([(CONTENT_TYPE, HeaderValue::from_static("image/png"))], bytes).into_response(),
+
+        // Counted but not on disk yet (the capture is still being written)
+        Err(_) => StatusCode::NOT_FOUND.into_response()
+    }
+}
+
+
+#[derive(Deserialize)]
+struct PinForm {
+    pin: String,
+    #[serde(default)]
+    view: AudienceView
+}
+
+async fn submit_pin(
+    State(current_state): State<utility::CurrentSlideState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Form(form): Form<PinForm>
+) -> Response {
+
+    unlock(&current_state, peer.ip(), &form.pin, form.view).await
+}
+
+
+// Hands out the access cookie for the right PIN and sends the browser back to the slides
+async fn unlock(current_state: &utility::CurrentSlideState, peer: IpAddr, candidate: &str, view: AudienceView) -> Response {
+
+    let pin = current_state.session_pin.lock().await.clone();
+
+    let Some(pin) = pin else {
+        // The PIN was turned off in the meantime
+        return Redirect::to(view.path()).into_response();
+    };
+
+    // Only a handful of guesses per address, then the waits get longer and longer
+    if let Err(wait) = access::count_pin_attempt(current_state, peer).await {
+
+        println!("Refused a PIN attempt from {}, locked out for another {}s.", peer, wait.as_secs());
+
+        let minutes = wait.as_secs().div_ceil(60);
+        let error = format!("Too many wrong PINs, try again in {} minute{}", minutes, if minutes == 1 { "" } else { "s" });
+
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(RETRY_AFTER, wait.as_secs().max(1).to_string())],
+            Html(pin_page(&error, view))
+        ).into_response();
+    }
+
+    if pin == candidate.trim() {
+
+        access::forget_pin_attempts(current_state, peer).await;
+
+        let cookie = access::access_cookie(current_state, &pin, current_state.use_tls.load(Ordering::SeqCst));
+
+        return ([(SET_COOKIE, cookie)], Redirect::to(view.path())).into_response();
+    }
+
+    // Slow down anyone trying to guess the PIN
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+    (StatusCode::UNAUTHORIZED, Html(pin_page("That PIN is not right, try again", view))).into_response()
+}
+
+
+fn pin_page(error: &str, view: AudienceView) -> String {
+
+    const PIN_HTML: &str = include_str!("../pin.html");
+
+    let error = if error.is_empty() { String::new() } else { format!("<p class=\"error\">{}</p>", error) };
+
+    let view = match view {
+        AudienceView::Slides => "slides",
+        AudienceView::Text => "text"
+    };
+
+    PIN_HTML
+        .replace("{{error}}", &error)
+        .replace("{{view}}", view)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    // Slide numbers no real session gets to, so the test files can't clash with captured ones
+    const SLIDE: u16 = 60003;
+    const DELETED_SLIDE: u16 = 60002;
+
+    async fn state() -> utility::CurrentSlideState {
+        let state = Arc::new(utility::AppState::new());
+        *state.slide_number.lock().await = SLIDE;
+        state.deleted_slides.lock().await.push(DELETED_SLIDE);
+        *state.ws_address.lock().await = Some("ws://10.0.0.2:4000".to_string());
+        state
+    }
+
+    // Requests as if they came from a phone in the audience
+    async fn get(state: &utility::CurrentSlideState, uri: &str) -> Response {
+        let mut request = Request::get(uri).body(Body::empty()).unwrap();
+        request.extensions_mut().insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 9], 50000))));
+
+        http_app(state.clone()).oneshot(request).await.unwrap()
+    }
+
+    async fn body(response: Response) -> String {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    #[tokio::test]
+    async fn address_js_is_generated() {
+        let state = state().await;
+
+        let response = get(&state, "/address.js").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/javascript");
+        assert_eq!(body(response).await, "export const webSocketAddress = \"ws://10.0.0.2:4000\";\n");
+
+        *state.ws_address.lock().await = None;
+        assert_eq!(get(&state, "/address.js").await.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn only_known_slides_are_served() {
+        let state = state().await;
+
+        let file = PathBuf::from("assets").join(format!("slide{}.png", SLIDE));
+        fs::create_dir_all("assets").unwrap();
+        fs::write(&file, b"png").unwrap();
+
+        let ok = get(&state, &format!("/slide{}.png", SLIDE)).await;
+        let by_id = get(&state, &format!("/slides/{}", SLIDE)).await.status();
+        let deleted = get(&state, &format!("/slide{}.png", DELETED_SLIDE)).await.status();
+        let deleted_by_id = get(&state, &format!("/slides/{}", DELETED_SLIDE)).await.status();
+        let unknown = get(&state, &format!("/slide{}.png", SLIDE + 1)).await.status();
+        let zero = get(&state, "/slide0.png").await.status();
+
+        fs::remove_file(&file).unwrap();
+        let _ = fs::remove_dir("assets"); // only when the test made it
+
+        assert_eq!(ok.status(), StatusCode::OK);
+        assert_eq!(ok.headers()[CONTENT_TYPE], "image/png");
+        assert_eq!(body(ok).await, "png");
+        assert_eq!(by_id, StatusCode::OK);
+        assert_eq!(deleted, StatusCode::GONE);
+        assert_eq!(deleted_by_id, StatusCode::GONE);
+        assert_eq!(unknown, StatusCode::NOT_FOUND);
+        assert_eq!(zero, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn session_files_are_not_served() {
+        let state = state().await;
+
+        for uri in [
+            "/manifest.json",
+            "/questions.json",
+            "/polls.json",
+            "/ocr-queue.json",
+            "/attendees/0123456789abcdef0123456789abcdef.json",
+            "/assets/manifest.json",
+            "/sessions/session-1.json",
+            "/slide1.thumb.png.json",
+        ] {
+            assert_eq!(get(&state, uri).await.status(), StatusCode::NOT_FOUND, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn encoded_traversal_is_rejected() {
+        let state = state().await;
+
+        for uri in [
+            "/%2e%2e%2fCargo.toml",
+            "/..%2fassets%2fmanifest.json",
+            "/slide%2e%2e%2f1.png",
+            "/slides/..%2f1",
+            "/slides/1%2f..%2f..%2fCargo.toml/thumbnail",
+        ] {
+            let status = get(&state, uri).await.status();
+            assert!(status == StatusCode::NOT_FOUND || status == StatusCode::BAD_REQUEST, "{} gave {}", uri, status);
+        }
+    }
+
+    #[tokio::test]
+    async fn slides_need_the_pin_when_there_is_one() {
+        let state = state().await;
+        *state.session_pin.lock().await = Some("123456".to_string());
+
+        assert_eq!(get(&state, &format!("/slides/{}", SLIDE)).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(get(&state, "/api/slides").await.status(), StatusCode::UNAUTHORIZED);
+
+        // The page itself still loads, to ask for the PIN
+        assert_eq!(get(&state, "/").await.status(), StatusCode::OK);
+    }
+}
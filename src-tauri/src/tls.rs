@@ -0,0 +1,195 @@
+use std::fs;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::Path;
+
+use rcgen::{CertificateParams, DnType, KeyPair, SanType};
+use rustls::pki_types::CertificateDer;
+use rustls::pki_types::pem::PemObject;
+use sha2::{Digest, Sha256};
+
+
+// Everything the TLS servers need, plus the fingerprint we show to the audience
+pub struct TlsMaterial {
+    pub cert_pem: String,
+    pub key_pem: String,
+    pub fingerprint: String
+}
+
+
+// Loads the certificate cached in `dir`, or creates a new self-signed one on first use.
+// The certificate is reused across sessions so the fingerprint attendees verify stays the same,
+// until the computer gets another address on the network and browsers would reject it anyway.
+pub fn load_or_create_certificate(dir: &Path, host_ip: IpAddr) -> Result<TlsMaterial, String> {
+
+    // rustls needs a process wide crypto provider, and it complains if we install it twice
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let cert_path = dir.join("cert.pem");
+    let key_path = dir.join("key.pem");
+
+    let (cert_pem, key_pem) = match (fs::read_to_string(&cert_path), fs::read_to_string(&key_path)) {
+
+        (Ok(cert), Ok(key)) if issued_for(&cert, host_ip) => (cert, key),
+
+        cached => {
+
+            if let (Ok(_), Ok(_)) = cached {
+                println!("The cached certificate isn't for {}, generating a new one.", host_ip);
+            } else {
+                println!("No cached certificate found, generating a new one.");
+            }
+
+            let key_pair = KeyPair::generate().map_err(|e| e.to_string())?;
+
+            let mut params = CertificateParams::new(vec!["localhost".to_string()])
+                .map_err(|e| e.to_string())?;
+
+            params.distinguished_name.push(DnType::CommonName, "Slideshow");
+            params.subject_alt_names.push(SanType::IpAddress(host_ip));
+
+            let cert = params.self_signed(&key_pair).map_err(|e| e.to_string())?;
+
+            let cert_pem = cert.pem();
+            let key_pem = key_pair.serialize_pem();
+
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            fs::write(&cert_path, &cert_pem).map_err(|e| e.to_string())?;
+            write_private(&key_path, &key_pem).map_err(|e| e.to_string())?;
+
+            (cert_pem, key_pem)
+        }
+    };
+
+    let cert_der = CertificateDer::from_pem_slice(cert_pem.as_bytes()).map_err(|e| e.to_string())?;
+
+    Ok(TlsMaterial {
+        fingerprint: fingerprint(&cert_der),
+        cert_pem,
+        key_pem
+    })
+}
+
+
+// Whether the certificate names the address attendees connect to. One that can't be read is treated as not
+fn issued_for(cert_pem: &str, host_ip: IpAddr) -> bool {
+
+    CertificateParams::from_ca_cert_pem(cert_pem)
+        .map(|params| params.subject_alt_names.contains(&SanType::IpAddress(host_ip)))
+        .unwrap_or(false)
+}
+
+
+// The private key is only for this user to read
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let mut file = fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+
+    // The mode only applies to new files, a key left from before keeps its own
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+
+    file.write_all(contents.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+
+    fs::File::create(path)?.write_all(contents.as_bytes())
+}
+
+
+// The SHA-256 fingerprint in the colon separated form browsers show in their certificate viewers
+fn fingerprint(cert_der: &[u8]) -> String {
+
+    Sha256::digest(cert_der)
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("slideshow-tls-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn the_cached_certificate_is_reused_for_the_same_address() {
+        let dir = temp_dir("reuse");
+        let ip: IpAddr = "192.168.1.20".parse().unwrap();
+
+        let first = load_or_create_certificate(&dir, ip).unwrap();
+        let second = load_or_create_certificate(&dir, ip).unwrap();
+
+        assert_eq!(first.fingerprint, second.fingerprint);
+        assert_eq!(first.key_pem, second.key_pem);
+        assert_eq!(fs::read_to_string(dir.join("cert.pem")).unwrap(), first.cert_pem);
+        assert!(issued_for(&first.cert_pem, ip));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_new_address_gets_a_new_certificate() {
+        let dir = temp_dir("new-address");
+        let old_ip: IpAddr = "192.168.1.20".parse().unwrap();
+        let new_ip: IpAddr = "10.0.0.7".parse().unwrap();
+
+        let old = load_or_create_certificate(&dir, old_ip).unwrap();
+        let new = load_or_create_certificate(&dir, new_ip).unwrap();
+
+        assert_ne!(old.fingerprint, new.fingerprint);
+        assert!(issued_for(&new.cert_pem, new_ip));
+        assert!(!issued_for(&new.cert_pem, old_ip));
+
+        // And it's the one cached from now on
+        assert_eq!(load_or_create_certificate(&dir, new_ip).unwrap().fingerprint, new.fingerprint);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn an_unreadable_cached_certificate_is_replaced() {
+        let dir = temp_dir("unreadable");
+        let ip: IpAddr = "192.168.1.20".parse().unwrap();
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("cert.pem"), "not a certificate").unwrap();
+        fs::write(dir.join("key.pem"), "not a key").unwrap();
+
+        let material = load_or_create_certificate(&dir, ip).unwrap();
+
+        assert!(issued_for(&material.cert_pem, ip));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn only_the_owner_can_read_the_key() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("key-mode");
+
+        // A key written before the mode was set, readable by everyone
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("key.pem"), "old").unwrap();
+        fs::set_permissions(dir.join("key.pem"), fs::Permissions::from_mode(0o644)).unwrap();
+
+        load_or_create_certificate(&dir, "192.168.1.20".parse().unwrap()).unwrap();
+
+        let mode = fs::metadata(dir.join("key.pem")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,14 +1,61 @@
-use tokio::sync::{Mutex, broadcast};
-use std::sync::Arc;
+use tokio::sync::{Mutex, Notify, broadcast, mpsc, watch};
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::AtomicBool;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::atomic::AtomicU64;
+use std::net::IpAddr;
 use serde::Serialize;
 use tauri::Emitter;
 
+use crate::access;
+use crate::manifest;
+use crate::protocol;
+use crate::clients;
+use crate::pointer;
+use crate::polls;
+use crate::questions;
+use crate::reactions;
+use crate::search;
+use crate::ocr;
+
 
 pub struct AppState {
     pub slide_number: Mutex<u16>, // the current slide number (for naming the slides)
-    pub broadcast_tx: broadcast::Sender<String>, // transmitter to send the slide addition message
+    pub broadcast_tx: broadcast::Sender<Broadcast>, // transmitter to send the slide addition message
+    pub broadcast_log: Mutex<BroadcastLog>, // the latest sequence number and recent broadcasts, for clients that reconnect
     pub deleted_slides: Mutex<Vec<u16>>, // holds the deleted slides
-    pub volatile_slide_number: Mutex<u16> // the slide at which the user is currently at (by using the arrow keys)
+    pub volatile_slide_number: Mutex<u16>, // the slide at which the user is currently at (by using the arrow keys)
+    pub shutdown_tx: watch::Sender<bool>, // flips to true to ask both servers and every WebSocket client to shut down
+    pub server_task: Mutex<Option<tokio::task::JoinHandle<()>>>, // the running setup_server task (None when stopped)
+    pub capture_enabled: AtomicBool, // whether key presses should trigger captures (the rdev thread can't be stopped)
+    pub key_listener_started: AtomicBool, // the rdev listener thread is only ever spawned once
+    pub use_tls: AtomicBool, // serve HTTPS/WSS with the self-signed certificate (remembered across restarts)
+    pub require_pin: AtomicBool, // whether audience clients have to enter a PIN to join
+    pub session_pin: Mutex<Option<String>>, // the PIN for this session (None when the session is open to everyone)
+    pub access_secret: [u8; 32], // signs the access cookies handed out for the right PIN
+    pub host_ip: Mutex<Option<IpAddr>>, // the address the servers are bound to
+    pub ws_address: Mutex<Option<String>>, // where the audience page should open its WebSocket (served as /address.js)
+    pub manifest: Mutex<manifest::SessionManifest>, // per-slide metadata of the current session (saved as assets/manifest.json)
+    pub clients: Mutex<HashMap<u64, clients::ClientInfo>>, // every connected WebSocket client, by client ID
+    pub next_client_id: AtomicU64, // the ID handed to the next client that connects
+    pub clients_notify: Notify, // poked whenever a client connects, leaves or reports something (for the dashboard)
+    pub blocked_addresses: Mutex<HashSet<IpAddr>>, // addresses the presenter blocked, refused by both servers
+    pub pin_attempts: Mutex<HashMap<IpAddr, access::PinAttempts>>, // PIN guesses per address, for the lockout
+    pub recent_disconnects: Mutex<VecDeque<clients::DisconnectRecord>>, // the latest connections that ended and why, newest first
+    pub pointer_relay: AtomicBool, // relay the mouse pointer to the audience while Ctrl is held
+    pub pointer: Mutex<pointer::PointerTracker>, // the state of the pointer relay
+    pub annotation_lock: Mutex<()>, // held while strokes change, so the annotated copies match the manifest
+    pub attendee_lock: Mutex<()>, // held while an attendee's notes are saved, so quick edits don't overwrite each other
+    pub ocr_queue: Mutex<VecDeque<ocr::QueuedSlide>>, // slides waiting for OCR, oldest first (saved as ocr/queue.json)
+    pub ocr_notify: Notify, // wakes the OCR worker when a slide is queued
+    pub search_index: Mutex<search::SearchIndex>, // the words on the slides of every session, read from the manifests on first search
+    pub questions: Mutex<questions::QuestionBoard>, // the audience's questions (saved as assets/questions.json)
+    pub questions_notify: Notify, // poked whenever the Q&A queue changes (for the presenter's queue)
+    pub polls: Mutex<polls::PollBoard>, // the polls put to the audience this session (saved as assets/polls.json)
+    pub polls_notify: Notify, // poked whenever a poll opens, closes or gets a vote (for the presenter's live results)
+    pub pending_reactions: Mutex<reactions::ReactionCounts>, // reactions that came in since the last flush, by slide
+    pub controller_key: Mutex<String>, // opens the remote control, never sent to the audience
+    pub control_tx: OnceLock<mpsc::UnboundedSender<protocol::ControlAction>> // feeds remote control actions to the key press worker
 }
 
 
@@ -17,6 +64,56 @@ pub type CurrentSlideState = Arc<AppState>;
 
 // Automatically sync the frontend with the volatile slide number so the user knows which slide they're at to sync it
 impl AppState {
+
+    // Everything empty, as the app starts up
+    pub fn new() -> Self {
+
+        let (broadcast_tx, rx) = broadcast::channel(100); // Buffer size of 100 messages
+
+        drop(rx);
+
+        let (shutdown_tx, _) = watch::channel(false);
+
+        AppState {
+            slide_number: Mutex::new(0),
+            broadcast_tx,
+            broadcast_log: Mutex::new(BroadcastLog::default()),
+            deleted_slides: Mutex::new(Vec::new()),
+            volatile_slide_number: Mutex::new(0),
+            shutdown_tx,
+            server_task: Mutex::new(None),
+            capture_enabled: AtomicBool::new(false),
+            key_listener_started: AtomicBool::new(false),
+            use_tls: AtomicBool::new(false),
+            require_pin: AtomicBool::new(false),
+            session_pin: Mutex::new(None),
+            access_secret: access::generate_secret(),
+            host_ip: Mutex::new(None),
+            ws_address: Mutex::new(None),
+            manifest: Mutex::new(manifest::SessionManifest::new()),
+            clients: Mutex::new(HashMap::new()),
+            next_client_id: AtomicU64::new(1),
+            clients_notify: Notify::new(),
+            blocked_addresses: Mutex::new(HashSet::new()),
+            pin_attempts: Mutex::new(HashMap::new()),
+            recent_disconnects: Mutex::new(VecDeque::new()),
+            pointer_relay: AtomicBool::new(false),
+            pointer: Mutex::new(pointer::PointerTracker::default()),
+            annotation_lock: Mutex::new(()),
+            attendee_lock: Mutex::new(()),
+            ocr_queue: Mutex::new(VecDeque::new()),
+            ocr_notify: Notify::new(),
+            search_index: Mutex::new(Default::default()),
+            questions: Mutex::new(questions::QuestionBoard::default()),
+            questions_notify: Notify::new(),
+            polls: Mutex::new(polls::PollBoard::default()),
+            polls_notify: Notify::new(),
+            pending_reactions: Mutex::new(BTreeMap::new()),
+            controller_key: Mutex::new(access::generate_controller_key()),
+            control_tx: OnceLock::new()
+        }
+    }
+
     pub async fn change_volatile_slide_number(&self, app: &tauri::AppHandle, increment: bool) {
 
         let mut num = self.volatile_slide_number.lock().await;
@@ -39,20 +136,248 @@ impl AppState {
         let new_value = *num;
 
         app.emit("volatile_slide_changed", new_value);
+
+        // Let the audience follow along as well
+        let message_construct = protocol::ServerMessage::PresenterPosition(protocol::PresenterPosition {
+            presenter_slide: new_value
+        });
+
+        // Nobody listening is fine, the position is also sent in slidesInfo when a client connects
+        let _ = self.broadcast(message_construct).await;
+    }
+
+    // Stamps the message with the next sequence number, keeps it for clients that reconnect, and sends it to everyone
+    pub async fn broadcast(&self, message: protocol::ServerMessage) -> Result<usize, broadcast::error::SendError<Broadcast>> {
+
+        // Holding the log while sending keeps the channel in sequence order
+        let mut log = self.broadcast_log.lock().await;
+
+        log.last_seq += 1;
+
+        let seq = log.last_seq;
+        let new_slide = match &message {
+            protocol::ServerMessage::SlideAdded(added) => Some(added.num_slides),
+            _ => None
+        };
+
+        let json = protocol::Envelope { seq: Some(seq), message }.to_json();
+
+        log.history.push_back(Broadcast { seq, json: json.clone(), new_slide, volatile: false });
+
+        if log.history.len() > BROADCAST_HISTORY {
+            log.history.pop_front();
+        }
+
+        self.broadcast_tx.send(Broadcast { seq, json, new_slide, volatile: false })
+    }
+
+    // For messages that only matter right now, like the laser pointer: they aren't numbered or kept for replays,
+    // so a burst of them can't push the real broadcasts out of the history
+    pub fn broadcast_volatile(&self, message: protocol::ServerMessage) -> Result<usize, broadcast::error::SendError<Broadcast>> {
+
+        let json = protocol::Envelope::direct(message).to_json();
+
+        self.broadcast_tx.send(Broadcast { seq: 0, json, new_slide: None, volatile: true })
+    }
+
+    // Subscribes to the broadcasts and returns what has to be sent to the client first:
+    // whatever it missed after `since` if we still have it, otherwise a fresh snapshot.
+    // Subscribing while holding the log means the receiver only ever sees broadcasts newer than those
+    pub async fn subscribe(&self, since: Option<u64>) -> (broadcast::Receiver<Broadcast>, Vec<Broadcast>) {
+
+        let (rx, replay) = {
+
+            let log = self.broadcast_log.lock().await;
+
+            (self.broadcast_tx.subscribe(), since.and_then(|since| log.replay_since(since)))
+        };
+
+        match replay {
+
+            Some(missed) => {
+                println!("Client resumed, replaying {} missed messages.", missed.len());
+                (rx, missed)
+            }
+
+            // The handshake, which also tells the client which protocol version we speak
+            None => (rx, vec![self.snapshot().await])
+        }
+    }
+
+    // The full state as a slidesInfo message, stamped with the last broadcast it already accounts for
+    pub async fn snapshot(&self) -> Broadcast {
+
+        // Read the sequence number first: anything broadcast after this is still delivered, and applying it twice is harmless
+        let seq = self.broadcast_log.lock().await.last_seq;
+
+        // Guards in the struct below live until the end of the statement, so each lock is only taken once
+        let (open_poll, poll_results) = {
+            let polls = self.polls.lock().await;
+            (polls.open_poll(), polls.published_results())
+        };
+
+        let (annotations, notes, texts) = {
+            let manifest = self.manifest.lock().await;
+
+            let annotations = manifest.slides.iter()
+                .filter(|slide| slide.deleted_at.is_none() && !slide.annotations.is_empty())
+                .map(|slide| (slide.id, slide.annotations.clone()))
+                .collect();
+
+            (annotations, manifest.published_notes(), manifest.slide_texts())
+        };
+
+        let message = protocol::ServerMessage::SlidesInfo(protocol::SlidesInfo {
+            protocol_version: protocol::PROTOCOL_VERSION,
+            num_slides: *self.slide_number.lock().await,
+            deleted_slides: self.deleted_slides.lock().await.clone(),
+            presenter_slide: *self.volatile_slide_number.lock().await,
+            annotations,
+            questions: self.questions.lock().await.public(),
+            open_poll,
+            poll_results,
+            notes,
+            texts
+        });
+
+        Broadcast { seq, json: protocol::Envelope { seq: Some(seq), message }.to_json(), new_slide: None, volatile: false }
     }
 }
 
 
-// The defined signature for the data sent by the server to the clients
-// Has three properties: [type of message, numSlides, deletedSlides]
-#[derive(Serialize)]
-pub struct ServerMessage {
-    pub message_type: String,
-    pub current_state: CurrentState
+// How many broadcasts we keep around for clients that reconnect
+const BROADCAST_HISTORY: usize = 256;
+
+// A broadcast message, already serialized, along with its sequence number
+#[derive(Clone, Debug)]
+pub struct Broadcast {
+    pub seq: u64,
+    pub json: String,
+    pub new_slide: Option<u16>, // set for slideAdded, so connections that want previews can push the thumbnail
+    pub volatile: bool // sent outside the sequence, seq is meaningless
+}
+
+impl Broadcast {
+
+    // Whether a client that got everything up to covered_seq (from a snapshot or a replay) already has this one
+    pub fn already_sent(&self, covered_seq: u64) -> bool {
+
+        !self.volatile && self.seq <= covered_seq
+    }
+}
+
+#[derive(Default)]
+pub struct BroadcastLog {
+    pub last_seq: u64,
+    pub history: VecDeque<Broadcast>
+}
+
+impl BroadcastLog {
+
+    // Everything broadcast after `since`, or None when some of it has already been dropped
+    // (or `since` comes from a different run of the app) and the client needs a snapshot instead
+    pub fn replay_since(&self, since: u64) -> Option<Vec<Broadcast>> {
+
+        if since > self.last_seq {
+            return None;
+        }
+
+        let oldest = self.history.front().map(|b| b.seq).unwrap_or(self.last_seq + 1);
+
+        if since + 1 < oldest {
+            return None;
+        }
+
+        Some(self.history.iter().filter(|b| b.seq > since).cloned().collect())
+    }
+}
+
+
+// Sent to the frontend with server_ready so it knows where (and how) the servers can be reached
+#[derive(Serialize, Clone)]
+pub struct ServerInfo {
+    pub address: String, // host:port shown to the presenter
+    pub url: String, // what the audience opens, http:// or https://
+    pub local_url: String, // what the presenter's own windows load the slides from
+    pub fingerprint: Option<String>, // SHA-256 fingerprint of the certificate in TLS mode
+    pub pin: Option<String> // the session PIN, if the audience has to enter one
 }
 
-#[derive(Serialize)]
-pub struct CurrentState {
-    pub num_slides: Option<u16>,
-    pub deleted_slides: Option<Vec<u16>>
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn slide(id: u16, text: Option<&str>) -> manifest::SlideEntry {
+        manifest::SlideEntry {
+            id,
+            file: format!("slide{}.png", id),
+            captured_at: 0,
+            width: 1920,
+            height: 1080,
+            thumbnail: None,
+            annotations: Vec::new(),
+            reactions: Default::default(),
+            notes: "Say hello".to_string(),
+            notes_published: true,
+            text: text.map(str::to_string),
+            text_status: crate::ocr::TextStatus::Done,
+            deleted_at: None
+        }
+    }
+
+    #[tokio::test]
+    async fn snapshot_does_not_wait_on_its_own_locks() {
+        let state = AppState::new();
+        *state.slide_number.lock().await = 2;
+        state.manifest.lock().await.slides = vec![slide(1, Some("Latency chart")), slide(2, None)];
+
+        let snapshot = tokio::time::timeout(Duration::from_secs(2), state.snapshot())
+            .await
+            .expect("snapshot() should not deadlock");
+
+        let json: serde_json::Value = serde_json::from_str(&snapshot.json).unwrap();
+        let info = &json["current_state"];
+
+        assert_eq!(json["message_type"], "slidesInfo");
+        assert_eq!(info["num_slides"], 2);
+        assert_eq!(info["texts"], serde_json::json!({ "1": "Latency chart" }));
+        assert_eq!(info["notes"]["2"], "Say hello");
+        assert_eq!(info["open_poll"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn replay_needs_the_whole_gap() {
+        let mut log = BroadcastLog::default();
+
+        for seq in 1..=3 {
+            log.last_seq = seq;
+            log.history.push_back(Broadcast { seq, json: String::new(), new_slide: None, volatile: false });
+        }
+
+        assert_eq!(log.replay_since(1).unwrap().iter().map(|b| b.seq).collect::<Vec<_>>(), vec![2, 3]);
+        assert!(log.replay_since(3).unwrap().is_empty());
+        assert!(log.replay_since(4).is_none()); // from a different run
+
+        log.history.pop_front();
+        assert!(log.replay_since(0).is_none()); // broadcast 1 is gone
+    }
+
+    #[tokio::test]
+    async fn reconnecting_clients_get_what_they_missed() {
+        let state = AppState::new();
+
+        for num_slides in 1..=2 {
+            let _ = state.broadcast(protocol::ServerMessage::SlideAdded(protocol::SlideAdded { num_slides })).await;
+        }
+
+        let (_rx, missed) = state.subscribe(Some(1)).await;
+        assert_eq!(missed.iter().map(|b| (b.seq, b.new_slide)).collect::<Vec<_>>(), vec![(2, Some(2))]);
+
+        // Nothing to resume from, so a snapshot that accounts for both
+        let (_rx, snapshot) = state.subscribe(None).await;
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].seq, 2);
+        assert!(snapshot[0].json.contains("slidesInfo"));
+    }
+}